@@ -0,0 +1,394 @@
+use std::{
+	io::{ErrorKind, Read, Write},
+	net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use super::{StrResult, serial::SerialCallback};
+
+const VERSION: u8 = 1;
+const JOYPAD: u8 = 101;
+const SYNC1: u8 = 104;
+const SYNC2: u8 = 105;
+const SYNC3: u8 = 106;
+const STATUS: u8 = 108;
+const WANT_DISCONNECT: u8 = 109;
+
+/// Timestamps count cycles at half the clock rate, and only have 31 bits.
+const TIMESTAMP_MASK: u32 = 0x7FFF_FFFF;
+
+/// How often, in clock cycles, the socket is checked for packets.
+const POLL_INTERVAL: u64 = 0x200;
+/// How often, in clock cycles, our timestamp is sent.
+const SYNC_INTERVAL: u64 = 0x4000;
+/// How far, in clock cycles, we may run ahead of the other side.
+const MAX_LEAD: u64 = 0x1_0000;
+
+/// A link cable to another emulator over TCP, speaking version 1.4 of the
+/// protocol BGB uses.
+///
+/// Both sides send their emulated time along, and whichever gets too far
+/// ahead waits for the other one to catch up.
+pub struct BgbLink {
+	stream: Option<TcpStream>,
+	buffer: Vec<u8>,
+	time: u64,
+	next_poll: u64,
+	next_sync: u64,
+	remote_time: u64,
+	last_timestamp: Option<u32>,
+	control: u8,
+	slave_byte: Option<u8>,
+	received: Option<(u8, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Packet {
+	command: u8,
+	b2: u8,
+	b3: u8,
+	b4: u8,
+	timestamp: u32,
+}
+
+impl Packet {
+	const fn new(command: u8, b2: u8, b3: u8, b4: u8, timestamp: u32) -> Self {
+		Self {
+			command,
+			b2,
+			b3,
+			b4,
+			timestamp,
+		}
+	}
+
+	const fn to_bytes(self) -> [u8; 8] {
+		let [t0, t1, t2, t3] = self.timestamp.to_le_bytes();
+		[self.command, self.b2, self.b3, self.b4, t0, t1, t2, t3]
+	}
+
+	const fn from_bytes(bytes: [u8; 8]) -> Self {
+		let [command, b2, b3, b4, t0, t1, t2, t3] = bytes;
+		Self::new(command, b2, b3, b4, u32::from_le_bytes([t0, t1, t2, t3]))
+	}
+}
+
+impl BgbLink {
+	/// Connects to an emulator listening at `address`.
+	pub fn connect(address: impl ToSocketAddrs) -> StrResult<Self> {
+		let stream =
+			TcpStream::connect(address).map_err(|_| "could not connect to link partner")?;
+		Self::handshake(stream)
+	}
+
+	/// Waits for an emulator to connect on `address`.
+	pub fn listen(address: impl ToSocketAddrs) -> StrResult<Self> {
+		let listener =
+			TcpListener::bind(address).map_err(|_| "could not listen for link partner")?;
+		let (stream, _) = listener
+			.accept()
+			.map_err(|_| "could not accept link partner")?;
+		Self::handshake(stream)
+	}
+
+	fn handshake(stream: TcpStream) -> StrResult<Self> {
+		stream
+			.set_nodelay(true)
+			.map_err(|_| "could not configure link socket")?;
+
+		let mut link = Self {
+			stream: Some(stream),
+			buffer: Vec::new(),
+			time: 0,
+			next_poll: 0,
+			next_sync: 0,
+			remote_time: 0,
+			last_timestamp: None,
+			control: 0,
+			slave_byte: None,
+			received: None,
+		};
+
+		link.send(Packet::new(VERSION, 1, 4, 0, 0));
+		match link.next_packet(true) {
+			Some(Packet {
+				command: VERSION,
+				b2: 1,
+				b3: 4,
+				b4: 0,
+				..
+			}) => {}
+			Some(_) => return Err("link partner uses an unsupported protocol version"),
+			None => return Err("link partner disconnected during handshake"),
+		}
+
+		// Running, and not paused.
+		link.send(Packet::new(STATUS, 0x01, 0, 0, 0));
+		Ok(link)
+	}
+
+	const fn timestamp(&self) -> u32 {
+		(self.time / 2) as u32 & TIMESTAMP_MASK
+	}
+
+	fn send(&mut self, packet: Packet) {
+		if let Some(stream) = &mut self.stream
+			&& stream.write_all(&packet.to_bytes()).is_err()
+		{
+			self.stream = None;
+		}
+	}
+
+	/// Reads what the socket has available, or waits for something when
+	/// `block` is set.
+	fn fill(&mut self, block: bool) {
+		let Some(stream) = &mut self.stream else {
+			return;
+		};
+
+		let mut bytes = [0; 64];
+		let result = stream
+			.set_nonblocking(!block)
+			.and_then(|()| stream.read(&mut bytes));
+
+		match result {
+			Ok(0) => self.stream = None,
+			Ok(n) => self.buffer.extend_from_slice(&bytes[..n]),
+			Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+			Err(_) => self.stream = None,
+		}
+	}
+
+	fn next_packet(&mut self, block: bool) -> Option<Packet> {
+		while self.buffer.len() < 8 {
+			let len = self.buffer.len();
+			self.fill(block);
+			if self.stream.is_none() || (!block && self.buffer.len() == len) {
+				return None;
+			}
+		}
+
+		let mut bytes = [0; 8];
+		bytes.copy_from_slice(&self.buffer[..8]);
+		self.buffer.drain(..8);
+		Some(Packet::from_bytes(bytes))
+	}
+
+	/// Keeps track of the other side's time. Its clock started at some other
+	/// point, so only how far it moved on is used.
+	fn note_timestamp(&mut self, timestamp: u32) {
+		match self.last_timestamp {
+			Some(last) => {
+				self.remote_time += u64::from(timestamp.wrapping_sub(last) & TIMESTAMP_MASK) * 2;
+			}
+			None => self.remote_time = self.time,
+		}
+
+		self.last_timestamp = Some(timestamp);
+	}
+
+	/// Handles a packet that isn't a reply to anything we sent.
+	fn handle(&mut self, packet: Packet) {
+		match packet.command {
+			SYNC1 => {
+				self.note_timestamp(packet.timestamp);
+				let timestamp = self.timestamp();
+				if let Some(value) = self.slave_byte.take() {
+					let period = if matches!(packet.b3 & 0x02, 0x02) {
+						16
+					} else {
+						512
+					};
+					self.received = Some((packet.b2, period));
+					self.send(Packet::new(SYNC2, value, 0x80, 0, timestamp));
+				} else {
+					self.send(Packet::new(SYNC3, 1, 0, 0, timestamp));
+				}
+			}
+			SYNC2 | SYNC3 => self.note_timestamp(packet.timestamp),
+			WANT_DISCONNECT => self.stream = None,
+			VERSION | JOYPAD | STATUS => {}
+			_ => self.disconnect(),
+		}
+	}
+
+	fn disconnect(&mut self) {
+		self.send(Packet::new(WANT_DISCONNECT, 0, 0, 0, 0));
+		self.stream = None;
+	}
+
+	fn poll(&mut self) {
+		while let Some(packet) = self.next_packet(false) {
+			self.handle(packet);
+		}
+	}
+
+	/// Waits while we are too far ahead of the other side.
+	fn wait_for_remote(&mut self) {
+		let timestamp = self.timestamp();
+		self.send(Packet::new(SYNC3, 0, 0, 0, timestamp));
+		self.next_sync = self.time + SYNC_INTERVAL;
+
+		while self.stream.is_some() && self.time > self.remote_time + MAX_LEAD {
+			if let Some(packet) = self.next_packet(true) {
+				self.handle(packet);
+			}
+		}
+	}
+}
+
+impl Drop for BgbLink {
+	fn drop(&mut self) {
+		self.disconnect();
+	}
+}
+
+impl SerialCallback for BgbLink {
+	fn call(&mut self, value: u8) -> Option<u8> {
+		let timestamp = self.timestamp();
+		let control = 0x81 | (self.control & 0x02);
+		self.send(Packet::new(SYNC1, value, control, 0, timestamp));
+
+		while self.stream.is_some() {
+			match self.next_packet(true)? {
+				Packet {
+					command: SYNC2,
+					b2,
+					timestamp,
+					..
+				} => {
+					self.note_timestamp(timestamp);
+					return Some(b2);
+				}
+				Packet {
+					command: SYNC3,
+					b2: 1,
+					..
+				} => return None,
+				packet => self.handle(packet),
+			}
+		}
+
+		None
+	}
+
+	fn write_control(&mut self, control: u8) {
+		self.control = control;
+		if !matches!(control & 0x81, 0x80) {
+			self.slave_byte = None;
+			self.received = None;
+		}
+	}
+
+	fn external_clock(&mut self, value: u8) -> Option<(u8, u32)> {
+		self.slave_byte = Some(value);
+		self.received.take()
+	}
+
+	fn advance(&mut self, ticks: u32) {
+		self.time += u64::from(ticks);
+
+		if self.stream.is_some() && self.time >= self.next_poll {
+			self.next_poll = self.time + POLL_INTERVAL;
+			self.poll();
+
+			if self.last_timestamp.is_some() && self.time > self.remote_time + MAX_LEAD {
+				self.wait_for_remote();
+			} else if self.time >= self.next_sync {
+				let timestamp = self.timestamp();
+				self.send(Packet::new(SYNC3, 0, 0, 0, timestamp));
+				self.next_sync = self.time + SYNC_INTERVAL;
+			}
+		}
+
+		// Only a transfer that is still waiting gets to answer.
+		self.slave_byte = None;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		io::{Read, Write},
+		net::{TcpListener, TcpStream},
+		thread,
+	};
+
+	use super::{BgbLink, Packet, STATUS, SYNC1, SYNC2, SYNC3, VERSION};
+	use crate::serial::SerialCallback;
+
+	/// Reads the next packet, skipping timestamp updates.
+	fn read(stream: &mut TcpStream) -> Packet {
+		loop {
+			let mut bytes = [0; 8];
+			stream.read_exact(&mut bytes).unwrap();
+			let packet = Packet::from_bytes(bytes);
+			if packet.command != SYNC3 || packet.b2 != 0 {
+				return packet;
+			}
+		}
+	}
+
+	fn write(stream: &mut TcpStream, packet: Packet) {
+		stream.write_all(&packet.to_bytes()).unwrap();
+	}
+
+	/// Accepts a connection and goes through the handshake as `version`.
+	fn peer(
+		version: u8,
+		run: impl FnOnce(TcpStream) + Send + 'static,
+	) -> (String, thread::JoinHandle<()>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap().to_string();
+
+		let handle = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			assert_eq!(read(&mut stream), Packet::new(VERSION, 1, 4, 0, 0));
+			write(&mut stream, Packet::new(VERSION, 1, version, 0, 0));
+			if version == 4 {
+				assert_eq!(read(&mut stream).command, STATUS);
+				run(stream);
+			}
+		});
+
+		(address, handle)
+	}
+
+	#[test]
+	fn master_transfer() {
+		let (address, handle) = peer(4, |mut stream| {
+			let sync1 = read(&mut stream);
+			assert_eq!((sync1.command, sync1.b2, sync1.b3), (SYNC1, 0x42, 0x81));
+			write(&mut stream, Packet::new(SYNC2, 0x99, 0x80, 0, 0));
+		});
+
+		let mut link = BgbLink::connect(address).unwrap();
+		assert_eq!(link.call(0x42), Some(0x99));
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn slave_transfer() {
+		let (address, handle) = peer(4, |mut stream| {
+			write(&mut stream, Packet::new(SYNC1, 0x55, 0x81, 0, 100));
+			let sync2 = read(&mut stream);
+			assert_eq!((sync2.command, sync2.b2), (SYNC2, 0x66));
+		});
+
+		let mut link = BgbLink::connect(address).unwrap();
+		let mut received = None;
+		while received.is_none() {
+			received = link.external_clock(0x66);
+			link.advance(4);
+		}
+
+		assert_eq!(received, Some((0x55, 512)));
+		handle.join().unwrap();
+	}
+
+	#[test]
+	fn version_mismatch() {
+		let (address, handle) = peer(3, |_| {});
+		assert!(BgbLink::connect(address).is_err());
+		handle.join().unwrap();
+	}
+}
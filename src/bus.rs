@@ -0,0 +1,77 @@
+/// The memory bus as seen from the [`CPU`](super::cpu::CPU).
+///
+/// [`MMU`](super::mmu::MMU) is the implementation that drives the rest of the
+/// system; anything else only needs to provide memory and interrupt lines.
+pub trait Bus {
+	fn rb(&mut self, address: u16) -> u8;
+
+	fn wb(&mut self, address: u16, value: u8);
+
+	/// Advances everything besides the CPU by `ticks` clock cycles, and
+	/// returns how many cycles passed at normal speed.
+	fn tick(&mut self, ticks: u32) -> u32;
+
+	/// Interrupts which are both enabled in IE and requested in IF.
+	fn pending_interrupts(&self) -> u8;
+
+	/// Clears interrupt `n` from IF once it is being serviced.
+	fn acknowledge_interrupt(&mut self, n: u32);
+
+	/// Called when the CPU executes `STOP`. Returns whether the CPU should
+	/// enter low-power mode, rather than having handled a speed switch.
+	fn stop(&mut self) -> bool {
+		true
+	}
+
+	/// Whether a button is held down, which wakes the CPU from `STOP`.
+	fn any_button_pressed(&self) -> bool {
+		false
+	}
+
+	/// Called when the CPU's 16-bit increment/decrement unit puts `address`
+	/// on the bus during the current M-cycle, either on its own or alongside
+	/// a read or write. On the DMG this can corrupt OAM.
+	fn inc_dec(&mut self, _address: u16) {}
+}
+
+/// Flat 64 KiB of RAM with IE and IF living at their usual addresses, for
+/// testing the CPU on its own.
+#[cfg(test)]
+pub struct FlatRam {
+	memory: Vec<u8>,
+	pub ticks: u32,
+}
+
+#[cfg(test)]
+impl FlatRam {
+	pub fn new() -> Self {
+		Self {
+			memory: vec![0; 0x10000],
+			ticks: 0,
+		}
+	}
+}
+
+#[cfg(test)]
+impl Bus for FlatRam {
+	fn rb(&mut self, address: u16) -> u8 {
+		self.memory[address as usize]
+	}
+
+	fn wb(&mut self, address: u16, value: u8) {
+		self.memory[address as usize] = value;
+	}
+
+	fn tick(&mut self, ticks: u32) -> u32 {
+		self.ticks += ticks;
+		ticks
+	}
+
+	fn pending_interrupts(&self) -> u8 {
+		self.memory[0xFFFF] & self.memory[0xFF0F] & 0x1F
+	}
+
+	fn acknowledge_interrupt(&mut self, n: u32) {
+		self.memory[0xFF0F] &= !(1 << n);
+	}
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+
+use super::mbc::MBC;
+
+/// The colorizations a Game Boy Color offers for classic games, picked by
+/// holding a direction and optionally A or B while the boot logo shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompatPalette {
+	Up,
+	UpA,
+	UpB,
+	Left,
+	LeftA,
+	LeftB,
+	Down,
+	DownA,
+	DownB,
+	Right,
+	RightA,
+	RightB,
+}
+
+impl CompatPalette {
+	const fn combination(self) -> usize {
+		match self {
+			Self::Up => 5,
+			Self::UpA => 43,
+			Self::UpB => 28,
+			Self::Left => 48,
+			Self::LeftA => 40,
+			Self::LeftB => 7,
+			Self::Down => 8,
+			Self::DownA => 3,
+			Self::DownB => 49,
+			Self::Right => 1,
+			Self::RightA => 0,
+			Self::RightB => 6,
+		}
+	}
+}
+
+/// The colors loaded into background palette 0 and object palettes 0 and 1,
+/// as RGB555 values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palettes {
+	pub bg: [u16; 4],
+	pub obj0: [u16; 4],
+	pub obj1: [u16; 4],
+}
+
+impl Palettes {
+	/// Picks the colors the CGB boot ROM would use for `cart`. Only games
+	/// published by Nintendo are recognized; everything else gets the default.
+	pub fn for_cartridge(cart: &dyn MBC) -> Self {
+		Self::combination(PALETTE_PER_CHECKSUM[checksum_index(cart).unwrap_or(0)])
+	}
+
+	#[must_use]
+	pub const fn manual(choice: CompatPalette) -> Self {
+		Self::combination(choice.combination())
+	}
+
+	const fn combination(index: usize) -> Self {
+		let (obj0, obj1, bg) = PALETTE_COMBINATIONS[index];
+		Self {
+			bg: colors(bg),
+			obj0: colors(obj0),
+			obj1: colors(obj1),
+		}
+	}
+}
+
+fn checksum_index(cart: &dyn MBC) -> Option<usize> {
+	let nintendo = match cart.read_rom(0x014B) {
+		0x33 => cart.read_rom(0x0144) == b'0' && cart.read_rom(0x0145) == b'1',
+		licensee => licensee == 0x01,
+	};
+
+	if !nintendo {
+		return None;
+	}
+
+	let checksum = (0x0134..=0x0143).fold(0u8, |sum, a| sum.wrapping_add(cart.read_rom(a)));
+	let letter = cart.read_rom(0x0137);
+
+	TITLE_CHECKSUMS.iter().enumerate().position(|(i, &c)| {
+		c == checksum
+			&& i.checked_sub(FIRST_DUPLICATE)
+				.is_none_or(|dup| DUPLICATE_LETTERS[dup] == letter)
+	})
+}
+
+/// Reads four consecutive colors from [`PALETTES`]. A few combinations start
+/// partway into a palette, so the offset is in colors rather than palettes.
+const fn colors(offset: usize) -> [u16; 4] {
+	[
+		PALETTES[offset],
+		PALETTES[offset + 1],
+		PALETTES[offset + 2],
+		PALETTES[offset + 3],
+	]
+}
+
+const FIRST_DUPLICATE: usize = 65;
+
+/// The sums of the title bytes the boot ROM recognizes. Entries from
+/// [`FIRST_DUPLICATE`] onwards are shared between games, and also need the
+/// fourth letter of the title to match [`DUPLICATE_LETTERS`].
+const TITLE_CHECKSUMS: [u8; 94] = [
+	0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+	0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+	0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+	0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+	0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+	0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+const DUPLICATE_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// The entry in [`PALETTE_COMBINATIONS`] for each of [`TITLE_CHECKSUMS`].
+const PALETTE_PER_CHECKSUM: [usize; 94] = [
+	0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+	5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+	5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+	24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// Color offsets into [`PALETTES`] for the first object palette, the second
+/// object palette and the background palette.
+const PALETTE_COMBINATIONS: [(usize, usize, usize); 51] = [
+	(16, 16, 116),
+	(72, 72, 72),
+	(80, 80, 80),
+	(96, 96, 96),
+	(36, 36, 36),
+	(0, 0, 0),
+	(108, 108, 108),
+	(20, 20, 20),
+	(48, 48, 48),
+	(104, 104, 104),
+	(64, 32, 32),
+	(16, 112, 112),
+	(16, 8, 8),
+	(12, 16, 16),
+	(16, 116, 116),
+	(112, 16, 112),
+	(8, 68, 8),
+	(64, 64, 32),
+	(16, 16, 28),
+	(16, 16, 72),
+	(16, 16, 80),
+	(76, 76, 36),
+	(15, 15, 44),
+	(68, 68, 8),
+	(16, 16, 8),
+	(16, 16, 12),
+	(112, 112, 0),
+	(12, 12, 0),
+	(0, 0, 4),
+	(72, 88, 72),
+	(80, 88, 80),
+	(96, 88, 96),
+	(64, 88, 32),
+	(68, 16, 52),
+	(111, 0, 56),
+	(111, 16, 60),
+	(76, 88, 36),
+	(64, 112, 40),
+	(16, 92, 112),
+	(68, 88, 8),
+	(16, 0, 8),
+	(16, 112, 12),
+	(112, 12, 0),
+	(12, 112, 16),
+	(84, 112, 16),
+	(12, 112, 0),
+	(100, 12, 112),
+	(0, 112, 32),
+	(16, 12, 112),
+	(112, 12, 24),
+	(16, 112, 116),
+];
+
+/// Thirty four-color palettes, stored back to back.
+const PALETTES: [u16; 120] = [
+	0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB, 0x7FFF, 0x6E31, 0x454A, 0x0000,
+	0x7FFF, 0x1BEF, 0x0200, 0x0000, 0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+	0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000, 0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+	0x7E74, 0x03FF, 0x0180, 0x0000, 0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+	0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0, 0x03ED, 0x7FFF, 0x255F, 0x0000,
+	0x036A, 0x021F, 0x03FF, 0x7FFF, 0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+	0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000, 0x7FFF, 0x027F, 0x001F, 0x0000,
+	0x7FFF, 0x03E0, 0x0206, 0x0120, 0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+	0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000, 0x7FFF, 0x033F, 0x0193, 0x0000,
+	0x0000, 0x4200, 0x037F, 0x7FFF, 0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+#[cfg(test)]
+mod tests {
+	use super::{CompatPalette, PALETTES, Palettes, colors};
+	use crate::mbc;
+
+	fn cart(title: &[u8], licensee: u8) -> Box<dyn mbc::MBC> {
+		let mut rom = vec![0; 0x8000];
+		rom[0x134..0x134 + title.len()].copy_from_slice(title);
+		rom[0x14B] = licensee;
+		mbc::get_mbc(rom, true).unwrap()
+	}
+
+	#[test]
+	fn title_lookup() {
+		let palettes = Palettes::for_cartridge(&*cart(b"POKEMON RED", 0x01));
+		assert_eq!(palettes.bg, colors(16));
+		assert_eq!(palettes.obj0, colors(12));
+
+		// Shares its checksum with other games, and is told apart by the 'E'.
+		let palettes = Palettes::for_cartridge(&*cart(b"POKEMON BLUE", 0x01));
+		assert_eq!(palettes.bg, colors(112));
+	}
+
+	#[test]
+	fn unknown_games_use_default() {
+		let default = Palettes::manual(CompatPalette::RightA);
+		assert_eq!(Palettes::for_cartridge(&*cart(b"CRANEBOY", 0x01)), default);
+		assert_eq!(
+			Palettes::for_cartridge(&*cart(b"POKEMON RED", 0x00)),
+			default
+		);
+	}
+
+	#[test]
+	fn manual_choice() {
+		let palettes = Palettes::manual(CompatPalette::RightB);
+		assert_eq!(palettes.bg, [0x0000, 0x4200, 0x037F, 0x7FFF]);
+		assert_eq!(
+			palettes.bg,
+			[PALETTES[108], PALETTES[109], PALETTES[110], PALETTES[111]]
+		);
+	}
+}
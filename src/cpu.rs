@@ -1,64 +1,121 @@
 use serde::{Deserialize, Serialize};
 
 use super::{
-	SerialCallback, StrResult, mbc,
+	SerialCallback, StrResult,
+	bus::Bus,
+	gb_mode::Model,
+	mbc,
 	mmu::MMU,
 	registers::{CpuFlags, Registers},
 };
 
 #[derive(Serialize, Deserialize)]
-pub struct CPU {
+pub struct CPU<B = MMU> {
 	reg: Registers,
-	pub mmu: MMU,
+	pub bus: B,
 	halted: bool,
 	halt_bug: bool,
+	stopped: bool,
+	locked: Option<Lockup>,
 	ime: bool,
 	setdi: u32,
 	setei: u32,
+	#[serde(skip)]
+	step_cycles: u32,
+	#[serde(skip)]
+	step_ticks: u32,
 }
 
-impl CPU {
+impl CPU<MMU> {
 	pub fn classic(
 		cart: Box<dyn mbc::MBC + 'static>,
+		model: Model,
+		boot_rom: Option<Vec<u8>>,
 		serial_callback: Option<Box<dyn SerialCallback>>,
 	) -> StrResult<Self> {
-		let mmu = MMU::classic(cart, serial_callback)?;
-		let registers = Registers::new(mmu.gbmode);
-
-		Ok(Self {
-			reg: registers,
-			halt_bug: false,
-			halted: false,
-			ime: true,
-			setdi: 0,
-			setei: 0,
-			mmu,
-		})
+		let mmu = MMU::classic(cart, model, boot_rom, serial_callback)?;
+		Ok(Self::with_mmu(mmu))
 	}
 
 	pub fn cgb(
 		cart: Box<dyn mbc::MBC + 'static>,
+		model: Model,
+		boot_rom: Option<Vec<u8>>,
 		serial_callback: Option<Box<dyn SerialCallback>>,
 	) -> StrResult<Self> {
-		let mmu = MMU::cgb(cart, serial_callback)?;
-		let registers = Registers::new(mmu.gbmode);
-		Ok(Self {
+		let mmu = MMU::cgb(cart, model, boot_rom, serial_callback)?;
+		Ok(Self::with_mmu(mmu))
+	}
+
+	fn with_mmu(mmu: MMU) -> Self {
+		let registers = if mmu.boot_rom_mapped() {
+			Registers::power_on()
+		} else {
+			Registers::new(mmu.model, mmu.gbmode)
+		};
+
+		Self::new(mmu, registers)
+	}
+}
+
+impl<B: Bus> CPU<B> {
+	pub const fn new(bus: B, registers: Registers) -> Self {
+		Self {
 			reg: registers,
 			halt_bug: false,
 			halted: false,
-			ime: true,
+			stopped: false,
+			locked: None,
+			// IME is clear at power-on, and no boot ROM sets it before jumping
+			// to the cartridge.
+			ime: false,
 			setdi: 0,
 			setei: 0,
-			mmu,
-		})
+			step_cycles: 0,
+			step_ticks: 0,
+			bus,
+		}
 	}
 
 	pub fn do_cycle(&mut self) -> u32 {
-		let ticks = self.get_ticks() * 4;
-		self.mmu.do_cycle(ticks)
+		self.step_cycles = 0;
+		self.step_ticks = 0;
+
+		// STOP halts the system clock, so nothing else moves on until a
+		// button is pressed. Time still passes for whoever is waiting.
+		if self.stopped {
+			if !self.bus.any_button_pressed() {
+				return 4;
+			}
+
+			self.stopped = false;
+		}
+
+		// Memory accesses tick the system as they happen, so only the internal
+		// cycles left over at the end of the instruction still need to run.
+		let cycles = self.get_ticks();
+		while self.step_cycles < cycles {
+			self.tick();
+		}
+
+		self.step_ticks
+	}
+
+	/// The illegal opcode which locked up the CPU, if one was executed.
+	pub const fn lockup(&self) -> Option<Lockup> {
+		self.locked
+	}
+
+	/// Whether STOP has halted the system clock.
+	pub const fn stopped(&self) -> bool {
+		self.stopped
 	}
 
 	fn get_ticks(&mut self) -> u32 {
+		if self.locked.is_some() {
+			return 1;
+		}
+
 		self.update_ime();
 
 		match self.handle_interrupt() {
@@ -74,8 +131,7 @@ impl CPU {
 			return 0;
 		}
 
-		let triggered = self.mmu.inte & self.mmu.intf & 0x1F;
-		if matches!(triggered, 0) {
+		if matches!(self.bus.pending_interrupts(), 0) {
 			return 0;
 		}
 
@@ -86,25 +142,69 @@ impl CPU {
 
 		self.ime = false;
 
-		let n = triggered.trailing_zeros();
-		if n >= 5 {
-			panic!("invalid interrupt triggered");
-		}
+		self.tick();
+		self.tick();
 
-		self.mmu.intf &= !(1 << n);
-		let pc = self.reg.pc;
-		self.push_stack(pc);
-		self.reg.pc = 0x0040 | ((n as u16) << 3);
+		let pc = if self.halt_bug {
+			// `EI` directly followed by `HALT` returns to the `HALT` itself.
+			self.halt_bug = false;
+			self.reg.pc.wrapping_sub(1)
+		} else {
+			self.reg.pc
+		};
 
-		4
+		self.reg.sp = self.reg.sp.wrapping_sub(1);
+		self.wb(self.reg.sp, (pc >> 8) as u8);
+
+		// The vector is only picked after the high byte of PC has been pushed.
+		// When that push lands on IE and clears every pending interrupt, the
+		// dispatch is cancelled and execution continues at 0x0000 instead.
+		let vector = match self.bus.pending_interrupts().trailing_zeros() {
+			n @ 0..5 => {
+				self.bus.acknowledge_interrupt(n);
+				0x0040 | ((n as u16) << 3)
+			}
+			_ => 0x0000,
+		};
+
+		self.reg.sp = self.reg.sp.wrapping_sub(1);
+		self.wb(self.reg.sp, (pc & 0xFF) as u8);
+		self.reg.pc = vector;
+
+		5
 	}
 
 	fn push_stack(&mut self, value: u16) {
-		self.reg.sp = self.reg.sp.wrapping_sub(2);
-		self.mmu.ww(self.reg.sp, value);
+		self.reg.sp = self.reg.sp.wrapping_sub(1);
+		self.wb(self.reg.sp, (value >> 8) as u8);
+		self.bus.inc_dec(self.reg.sp);
+		self.reg.sp = self.reg.sp.wrapping_sub(1);
+		self.wb(self.reg.sp, (value & 0xFF) as u8);
+	}
+
+	/// Advances the rest of the system by a single M-cycle.
+	fn tick(&mut self) {
+		self.step_cycles += 1;
+		self.step_ticks += self.bus.tick(4);
+	}
+
+	/// An internal M-cycle spent incrementing or decrementing `address`.
+	fn tick_inc_dec(&mut self, address: u16) {
+		self.tick();
+		self.bus.inc_dec(address);
 	}
 
-	fn update_ime(&mut self) {
+	fn rb(&mut self, address: u16) -> u8 {
+		self.tick();
+		self.bus.rb(address)
+	}
+
+	fn wb(&mut self, address: u16, value: u8) {
+		self.tick();
+		self.bus.wb(address, value);
+	}
+
+	const fn update_ime(&mut self) {
 		self.setdi = match self.setdi {
 			2 => 1,
 			1 => {
@@ -124,7 +224,1343 @@ impl CPU {
 		}
 	}
 
+	fn fetch_byte(&mut self) -> u8 {
+		let b = self.rb(self.reg.pc);
+		if self.halt_bug {
+			self.halt_bug = false;
+		} else {
+			self.reg.pc = self.reg.pc.wrapping_add(1);
+		}
+		b
+	}
+
+	fn fetch_word(&mut self) -> u16 {
+		let lo = self.fetch_byte();
+		let hi = self.fetch_byte();
+		u16::from_le_bytes([lo, hi])
+	}
+
+	fn pop_stack(&mut self) -> u16 {
+		let lo = self.rb(self.reg.sp);
+		self.bus.inc_dec(self.reg.sp);
+		self.reg.sp = self.reg.sp.wrapping_add(1);
+		let hi = self.rb(self.reg.sp);
+		self.bus.inc_dec(self.reg.sp);
+		self.reg.sp = self.reg.sp.wrapping_add(1);
+		u16::from_le_bytes([lo, hi])
+	}
+
+	/// Reads one of the eight 8-bit operands encoded in the low three bits of
+	/// an opcode, in the order B, C, D, E, H, L, (HL), A.
+	fn get_r8(&mut self, index: u8) -> u8 {
+		match index & 0x07 {
+			0 => self.reg.b,
+			1 => self.reg.c,
+			2 => self.reg.d,
+			3 => self.reg.e,
+			4 => self.reg.h,
+			5 => self.reg.l,
+			6 => self.rb(self.reg.hl()),
+			_ => self.reg.a,
+		}
+	}
+
+	fn set_r8(&mut self, index: u8, value: u8) {
+		match index & 0x07 {
+			0 => self.reg.b = value,
+			1 => self.reg.c = value,
+			2 => self.reg.d = value,
+			3 => self.reg.e = value,
+			4 => self.reg.h = value,
+			5 => self.reg.l = value,
+			6 => self.wb(self.reg.hl(), value),
+			_ => self.reg.a = value,
+		}
+	}
+
+	const fn condition(&self, opcode: u8) -> bool {
+		match (opcode >> 3) & 0x03 {
+			0 => !self.reg.get_flag(CpuFlags::Z),
+			1 => self.reg.get_flag(CpuFlags::Z),
+			2 => !self.reg.get_flag(CpuFlags::C),
+			_ => self.reg.get_flag(CpuFlags::C),
+		}
+	}
+
 	fn call(&mut self) -> u32 {
-		todo!()
+		let pc = self.reg.pc;
+		let opcode = self.fetch_byte();
+		match opcode {
+			0x00 => 1,
+			0x01 => {
+				let v = self.fetch_word();
+				self.reg.set_bc(v);
+				3
+			}
+			0x02 => {
+				self.wb(self.reg.bc(), self.reg.a);
+				2
+			}
+			0x03 => {
+				self.tick_inc_dec(self.reg.bc());
+				self.reg.set_bc(self.reg.bc().wrapping_add(1));
+				2
+			}
+			0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x3C => {
+				let r = opcode >> 3;
+				let v = self.get_r8(r);
+				let v = self.alu_inc(v);
+				self.set_r8(r, v);
+				1
+			}
+			0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x3D => {
+				let r = opcode >> 3;
+				let v = self.get_r8(r);
+				let v = self.alu_dec(v);
+				self.set_r8(r, v);
+				1
+			}
+			0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x3E => {
+				let v = self.fetch_byte();
+				self.set_r8(opcode >> 3, v);
+				2
+			}
+			0x07 => {
+				self.reg.a = self.alu_rlc(self.reg.a);
+				self.reg.flag(CpuFlags::Z, false);
+				1
+			}
+			0x08 => {
+				let a = self.fetch_word();
+				self.wb(a, (self.reg.sp & 0xFF) as u8);
+				self.wb(a.wrapping_add(1), (self.reg.sp >> 8) as u8);
+				5
+			}
+			0x09 => {
+				self.alu_add16(self.reg.bc());
+				2
+			}
+			0x0A => {
+				self.reg.a = self.rb(self.reg.bc());
+				2
+			}
+			0x0B => {
+				self.tick_inc_dec(self.reg.bc());
+				self.reg.set_bc(self.reg.bc().wrapping_sub(1));
+				2
+			}
+			0x0F => {
+				self.reg.a = self.alu_rrc(self.reg.a);
+				self.reg.flag(CpuFlags::Z, false);
+				1
+			}
+			0x10 => {
+				// STOP is followed by a padding byte which is skipped.
+				self.fetch_byte();
+				self.cpu_stop();
+				1
+			}
+			0x11 => {
+				let v = self.fetch_word();
+				self.reg.set_de(v);
+				3
+			}
+			0x12 => {
+				self.wb(self.reg.de(), self.reg.a);
+				2
+			}
+			0x13 => {
+				self.tick_inc_dec(self.reg.de());
+				self.reg.set_de(self.reg.de().wrapping_add(1));
+				2
+			}
+			0x17 => {
+				self.reg.a = self.alu_rl(self.reg.a);
+				self.reg.flag(CpuFlags::Z, false);
+				1
+			}
+			0x18 => {
+				self.cpu_jr();
+				3
+			}
+			0x19 => {
+				self.alu_add16(self.reg.de());
+				2
+			}
+			0x1A => {
+				self.reg.a = self.rb(self.reg.de());
+				2
+			}
+			0x1B => {
+				self.tick_inc_dec(self.reg.de());
+				self.reg.set_de(self.reg.de().wrapping_sub(1));
+				2
+			}
+			0x1F => {
+				self.reg.a = self.alu_rr(self.reg.a);
+				self.reg.flag(CpuFlags::Z, false);
+				1
+			}
+			0x20 | 0x28 | 0x30 | 0x38 => {
+				if self.condition(opcode) {
+					self.cpu_jr();
+					3
+				} else {
+					self.fetch_byte();
+					2
+				}
+			}
+			0x21 => {
+				let v = self.fetch_word();
+				self.reg.set_hl(v);
+				3
+			}
+			0x22 => {
+				let a = self.reg.hli();
+				self.wb(a, self.reg.a);
+				self.bus.inc_dec(a);
+				2
+			}
+			0x23 => {
+				self.tick_inc_dec(self.reg.hl());
+				self.reg.set_hl(self.reg.hl().wrapping_add(1));
+				2
+			}
+			0x27 => {
+				self.alu_daa();
+				1
+			}
+			0x29 => {
+				self.alu_add16(self.reg.hl());
+				2
+			}
+			0x2A => {
+				let a = self.reg.hli();
+				self.reg.a = self.rb(a);
+				self.bus.inc_dec(a);
+				2
+			}
+			0x2B => {
+				self.tick_inc_dec(self.reg.hl());
+				self.reg.set_hl(self.reg.hl().wrapping_sub(1));
+				2
+			}
+			0x2F => {
+				self.reg.a = !self.reg.a;
+				self.reg.flag(CpuFlags::H | CpuFlags::N, true);
+				1
+			}
+			0x31 => {
+				self.reg.sp = self.fetch_word();
+				3
+			}
+			0x32 => {
+				let a = self.reg.hld();
+				self.wb(a, self.reg.a);
+				self.bus.inc_dec(a);
+				2
+			}
+			0x33 => {
+				self.tick_inc_dec(self.reg.sp);
+				self.reg.sp = self.reg.sp.wrapping_add(1);
+				2
+			}
+			0x34 => {
+				let a = self.reg.hl();
+				let v = self.rb(a);
+				let v = self.alu_inc(v);
+				self.wb(a, v);
+				3
+			}
+			0x35 => {
+				let a = self.reg.hl();
+				let v = self.rb(a);
+				let v = self.alu_dec(v);
+				self.wb(a, v);
+				3
+			}
+			0x36 => {
+				let v = self.fetch_byte();
+				self.wb(self.reg.hl(), v);
+				3
+			}
+			0x37 => {
+				self.reg.flag(CpuFlags::C, true);
+				self.reg.flag(CpuFlags::H | CpuFlags::N, false);
+				1
+			}
+			0x39 => {
+				self.alu_add16(self.reg.sp);
+				2
+			}
+			0x3A => {
+				let a = self.reg.hld();
+				self.reg.a = self.rb(a);
+				self.bus.inc_dec(a);
+				2
+			}
+			0x3B => {
+				self.tick_inc_dec(self.reg.sp);
+				self.reg.sp = self.reg.sp.wrapping_sub(1);
+				2
+			}
+			0x3F => {
+				let c = self.reg.get_flag(CpuFlags::C);
+				self.reg.flag(CpuFlags::C, !c);
+				self.reg.flag(CpuFlags::H | CpuFlags::N, false);
+				1
+			}
+			0x76 => {
+				if !self.ime && !matches!(self.bus.pending_interrupts(), 0) {
+					self.halt_bug = true;
+				} else {
+					self.halted = true;
+				}
+				1
+			}
+			0x40..=0x7F => {
+				let v = self.get_r8(opcode);
+				self.set_r8(opcode >> 3, v);
+				if matches!(opcode & 0x07, 6) || matches!((opcode >> 3) & 0x07, 6) {
+					2
+				} else {
+					1
+				}
+			}
+			0x80..=0xBF => {
+				let v = self.get_r8(opcode);
+				self.alu_op(opcode >> 3, v);
+				if matches!(opcode & 0x07, 6) { 2 } else { 1 }
+			}
+			0xC0 | 0xC8 | 0xD0 | 0xD8 => {
+				self.tick();
+				if self.condition(opcode) {
+					self.reg.pc = self.pop_stack();
+					5
+				} else {
+					2
+				}
+			}
+			0xC1 => {
+				let v = self.pop_stack();
+				self.reg.set_bc(v);
+				3
+			}
+			0xC2 | 0xCA | 0xD2 | 0xDA => {
+				let a = self.fetch_word();
+				if self.condition(opcode) {
+					self.reg.pc = a;
+					4
+				} else {
+					3
+				}
+			}
+			0xC3 => {
+				self.reg.pc = self.fetch_word();
+				4
+			}
+			0xC4 | 0xCC | 0xD4 | 0xDC => {
+				let a = self.fetch_word();
+				if self.condition(opcode) {
+					self.tick_inc_dec(self.reg.sp);
+					self.push_stack(self.reg.pc);
+					self.reg.pc = a;
+					6
+				} else {
+					3
+				}
+			}
+			0xC5 => {
+				self.tick_inc_dec(self.reg.sp);
+				self.push_stack(self.reg.bc());
+				4
+			}
+			0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+				let v = self.fetch_byte();
+				self.alu_op(opcode >> 3, v);
+				2
+			}
+			0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+				self.tick_inc_dec(self.reg.sp);
+				self.push_stack(self.reg.pc);
+				self.reg.pc = u16::from(opcode & 0x38);
+				4
+			}
+			0xC9 => {
+				self.reg.pc = self.pop_stack();
+				4
+			}
+			0xCB => self.call_cb(),
+			0xCD => {
+				let a = self.fetch_word();
+				self.tick_inc_dec(self.reg.sp);
+				self.push_stack(self.reg.pc);
+				self.reg.pc = a;
+				6
+			}
+			0xD1 => {
+				let v = self.pop_stack();
+				self.reg.set_de(v);
+				3
+			}
+			0xD5 => {
+				self.tick_inc_dec(self.reg.sp);
+				self.push_stack(self.reg.de());
+				4
+			}
+			0xD9 => {
+				self.reg.pc = self.pop_stack();
+				self.setei = 1;
+				4
+			}
+			0xE0 => {
+				let a = 0xFF00 | u16::from(self.fetch_byte());
+				self.wb(a, self.reg.a);
+				3
+			}
+			0xE1 => {
+				let v = self.pop_stack();
+				self.reg.set_hl(v);
+				3
+			}
+			0xE2 => {
+				self.wb(0xFF00 | u16::from(self.reg.c), self.reg.a);
+				2
+			}
+			0xE5 => {
+				self.tick_inc_dec(self.reg.sp);
+				self.push_stack(self.reg.hl());
+				4
+			}
+			0xE8 => {
+				self.reg.sp = self.alu_add16imm(self.reg.sp);
+				4
+			}
+			0xE9 => {
+				self.reg.pc = self.reg.hl();
+				1
+			}
+			0xEA => {
+				let a = self.fetch_word();
+				self.wb(a, self.reg.a);
+				4
+			}
+			0xF0 => {
+				let a = 0xFF00 | u16::from(self.fetch_byte());
+				self.reg.a = self.rb(a);
+				3
+			}
+			0xF1 => {
+				let v = self.pop_stack();
+				self.reg.set_af(v);
+				3
+			}
+			0xF2 => {
+				self.reg.a = self.rb(0xFF00 | u16::from(self.reg.c));
+				2
+			}
+			0xF3 => {
+				self.setdi = 2;
+				1
+			}
+			0xF5 => {
+				self.tick_inc_dec(self.reg.sp);
+				self.push_stack(self.reg.af());
+				4
+			}
+			0xF8 => {
+				let v = self.alu_add16imm(self.reg.sp);
+				self.reg.set_hl(v);
+				3
+			}
+			0xF9 => {
+				self.reg.sp = self.reg.hl();
+				2
+			}
+			0xFA => {
+				let a = self.fetch_word();
+				self.reg.a = self.rb(a);
+				4
+			}
+			0xFB => {
+				self.setei = 2;
+				1
+			}
+			0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB..=0xED | 0xF4 | 0xFC | 0xFD => {
+				self.locked = Some(Lockup { pc, opcode });
+				1
+			}
+		}
+	}
+
+	fn cpu_stop(&mut self) {
+		if self.bus.stop() {
+			self.stopped = true;
+		}
+	}
+
+	fn call_cb(&mut self) -> u32 {
+		let opcode = self.fetch_byte();
+		let r = opcode & 0x07;
+		let bit = (opcode >> 3) & 0x07;
+		let v = self.get_r8(r);
+
+		match opcode >> 6 {
+			0 => {
+				let v = match bit {
+					0 => self.alu_rlc(v),
+					1 => self.alu_rrc(v),
+					2 => self.alu_rl(v),
+					3 => self.alu_rr(v),
+					4 => self.alu_sla(v),
+					5 => self.alu_sra(v),
+					6 => self.alu_swap(v),
+					_ => self.alu_srl(v),
+				};
+				self.set_r8(r, v);
+			}
+			1 => {
+				self.alu_bit(v, bit);
+				return if matches!(r, 6) { 3 } else { 2 };
+			}
+			2 => self.set_r8(r, v & !(1 << bit)),
+			_ => self.set_r8(r, v | (1 << bit)),
+		}
+
+		if matches!(r, 6) { 4 } else { 2 }
+	}
+
+	fn alu_op(&mut self, op: u8, b: u8) {
+		match op & 0x07 {
+			0 => self.alu_add(b, false),
+			1 => self.alu_add(b, true),
+			2 => self.alu_sub(b, false),
+			3 => self.alu_sub(b, true),
+			4 => self.alu_and(b),
+			5 => self.alu_xor(b),
+			6 => self.alu_or(b),
+			_ => self.alu_cp(b),
+		}
+	}
+
+	fn alu_add(&mut self, b: u8, usec: bool) {
+		let c = u8::from(usec && self.reg.get_flag(CpuFlags::C));
+		let a = self.reg.a;
+		let r = a.wrapping_add(b).wrapping_add(c);
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg.flag(CpuFlags::H, (a & 0xF) + (b & 0xF) + c > 0xF);
+		self.reg.flag(CpuFlags::N, false);
+		self.reg.flag(
+			CpuFlags::C,
+			u16::from(a) + u16::from(b) + u16::from(c) > 0xFF,
+		);
+		self.reg.a = r;
+	}
+
+	fn alu_sub(&mut self, b: u8, usec: bool) {
+		let c = u8::from(usec && self.reg.get_flag(CpuFlags::C));
+		let a = self.reg.a;
+		let r = a.wrapping_sub(b).wrapping_sub(c);
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg.flag(CpuFlags::H, (a & 0x0F) < (b & 0x0F) + c);
+		self.reg.flag(CpuFlags::N, true);
+		self.reg
+			.flag(CpuFlags::C, u16::from(a) < u16::from(b) + u16::from(c));
+		self.reg.a = r;
+	}
+
+	fn alu_and(&mut self, b: u8) {
+		let r = self.reg.a & b;
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg.flag(CpuFlags::H, true);
+		self.reg.flag(CpuFlags::C | CpuFlags::N, false);
+		self.reg.a = r;
+	}
+
+	fn alu_or(&mut self, b: u8) {
+		let r = self.reg.a | b;
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg
+			.flag(CpuFlags::C | CpuFlags::H | CpuFlags::N, false);
+		self.reg.a = r;
+	}
+
+	fn alu_xor(&mut self, b: u8) {
+		let r = self.reg.a ^ b;
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg
+			.flag(CpuFlags::C | CpuFlags::H | CpuFlags::N, false);
+		self.reg.a = r;
+	}
+
+	fn alu_cp(&mut self, b: u8) {
+		let r = self.reg.a;
+		self.alu_sub(b, false);
+		self.reg.a = r;
+	}
+
+	fn alu_inc(&mut self, a: u8) -> u8 {
+		let r = a.wrapping_add(1);
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg.flag(CpuFlags::H, (a & 0x0F) + 1 > 0x0F);
+		self.reg.flag(CpuFlags::N, false);
+		r
+	}
+
+	fn alu_dec(&mut self, a: u8) -> u8 {
+		let r = a.wrapping_sub(1);
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg.flag(CpuFlags::H, matches!(a & 0x0F, 0));
+		self.reg.flag(CpuFlags::N, true);
+		r
+	}
+
+	fn alu_add16(&mut self, b: u16) {
+		let a = self.reg.hl();
+		let r = a.wrapping_add(b);
+		self.reg
+			.flag(CpuFlags::H, (a & 0x0FFF) + (b & 0x0FFF) > 0x0FFF);
+		self.reg.flag(CpuFlags::N, false);
+		self.reg.flag(CpuFlags::C, a > 0xFFFF - b);
+		self.reg.set_hl(r);
+	}
+
+	fn alu_add16imm(&mut self, a: u16) -> u16 {
+		let b = i16::from(self.fetch_byte() as i8) as u16;
+		self.reg.flag(CpuFlags::Z | CpuFlags::N, false);
+		self.reg
+			.flag(CpuFlags::H, (a & 0x000F) + (b & 0x000F) > 0x000F);
+		self.reg
+			.flag(CpuFlags::C, (a & 0x00FF) + (b & 0x00FF) > 0x00FF);
+		a.wrapping_add(b)
+	}
+
+	fn alu_daa(&mut self) {
+		let mut a = self.reg.a;
+		let mut adjust = if self.reg.get_flag(CpuFlags::C) {
+			0x60
+		} else {
+			0x00
+		};
+
+		if self.reg.get_flag(CpuFlags::H) {
+			adjust |= 0x06;
+		}
+
+		if self.reg.get_flag(CpuFlags::N) {
+			a = a.wrapping_sub(adjust);
+		} else {
+			if a & 0x0F > 0x09 {
+				adjust |= 0x06;
+			}
+
+			if a > 0x99 {
+				adjust |= 0x60;
+			}
+
+			a = a.wrapping_add(adjust);
+		}
+
+		self.reg.flag(CpuFlags::C, adjust >= 0x60);
+		self.reg.flag(CpuFlags::H, false);
+		self.reg.flag(CpuFlags::Z, matches!(a, 0));
+		self.reg.a = a;
+	}
+
+	fn alu_srflagupdate(&mut self, r: u8, c: bool) {
+		self.reg.flag(CpuFlags::H | CpuFlags::N, false);
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+		self.reg.flag(CpuFlags::C, c);
+	}
+
+	fn alu_rlc(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x80, 0x80);
+		let r = a.rotate_left(1);
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_rl(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x80, 0x80);
+		let r = (a << 1) | u8::from(self.reg.get_flag(CpuFlags::C));
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_rrc(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x01, 0x01);
+		let r = a.rotate_right(1);
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_rr(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x01, 0x01);
+		let r = (a >> 1)
+			| if self.reg.get_flag(CpuFlags::C) {
+				0x80
+			} else {
+				0
+			};
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_sla(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x80, 0x80);
+		let r = a << 1;
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_sra(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x01, 0x01);
+		let r = (a >> 1) | (a & 0x80);
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_swap(&mut self, a: u8) -> u8 {
+		let r = a.rotate_left(4);
+		self.alu_srflagupdate(r, false);
+		r
+	}
+
+	fn alu_srl(&mut self, a: u8) -> u8 {
+		let c = matches!(a & 0x01, 0x01);
+		let r = a >> 1;
+		self.alu_srflagupdate(r, c);
+		r
+	}
+
+	fn alu_bit(&mut self, a: u8, b: u8) {
+		let r = a & (1 << b);
+		self.reg.flag(CpuFlags::N, false);
+		self.reg.flag(CpuFlags::H, true);
+		self.reg.flag(CpuFlags::Z, matches!(r, 0));
+	}
+
+	fn cpu_jr(&mut self) {
+		let n = self.fetch_byte() as i8;
+		self.reg.pc = self.reg.pc.wrapping_add(n as u16);
+	}
+}
+
+/// The state a CPU hangs in after executing one of the unused opcodes. Only
+/// the CPU stops, the rest of the system keeps running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockup {
+	pub pc: u16,
+	pub opcode: u8,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CPU, CpuFlags, Lockup, Registers};
+	use crate::{
+		KeypadKey,
+		bus::{Bus, FlatRam},
+		gb_mode::{GbMode, Model},
+		mbc,
+	};
+
+	struct State {
+		af: u16,
+		bc: u16,
+		de: u16,
+		hl: u16,
+		pc: u16,
+		sp: u16,
+		ram: &'static [(u16, u8)],
+	}
+
+	/// Runs a single instruction against flat memory, and compares the
+	/// resulting registers, memory and M-cycle count.
+	fn run_vector(initial: &State, expected: &State, cycles: u32) {
+		let mut reg = Registers::new(Model::Dmg, GbMode::Classic);
+		reg.set_af(initial.af);
+		reg.set_bc(initial.bc);
+		reg.set_de(initial.de);
+		reg.set_hl(initial.hl);
+		reg.pc = initial.pc;
+		reg.sp = initial.sp;
+
+		let mut cpu = CPU::new(FlatRam::new(), reg);
+		for &(address, value) in initial.ram {
+			cpu.bus.wb(address, value);
+		}
+
+		assert_eq!(cpu.do_cycle(), cycles * 4);
+		assert_eq!(cpu.bus.ticks, cycles * 4);
+
+		assert_eq!(cpu.reg.af(), expected.af);
+		assert_eq!(cpu.reg.bc(), expected.bc);
+		assert_eq!(cpu.reg.de(), expected.de);
+		assert_eq!(cpu.reg.hl(), expected.hl);
+		assert_eq!(cpu.reg.pc, expected.pc);
+		assert_eq!(cpu.reg.sp, expected.sp);
+		for &(address, value) in expected.ram {
+			assert_eq!(cpu.bus.rb(address), value, "memory at {address:04X}");
+		}
+	}
+
+	fn load_program(mut cpu: CPU, program: &[u8]) -> CPU {
+		for (i, &b) in program.iter().enumerate() {
+			cpu.bus.wb(0xC000 + i as u16, b);
+		}
+
+		cpu.reg.pc = 0xC000;
+		cpu.bus.intf = 0x00;
+		cpu
+	}
+
+	fn cpu_with_program(program: &[u8]) -> CPU {
+		let cart = mbc::get_mbc(vec![0; 0x8000], true).unwrap();
+		load_program(CPU::classic(cart, Model::Dmg, None, None).unwrap(), program)
+	}
+
+	fn cgb_with_program(program: &[u8]) -> CPU {
+		let mut rom = vec![0; 0x8000];
+		rom[0x143] = 0x80;
+		let cart = mbc::get_mbc(rom, true).unwrap();
+		load_program(CPU::cgb(cart, Model::Cgb, None, None).unwrap(), program)
+	}
+
+	#[test]
+	fn conformance_vectors() {
+		// ADD A, B
+		run_vector(
+			&State {
+				af: 0x3A00,
+				bc: 0xC600,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0xFFFE,
+				ram: &[(0x0100, 0x80)],
+			},
+			&State {
+				af: 0x00B0,
+				bc: 0xC600,
+				de: 0,
+				hl: 0,
+				pc: 0x0101,
+				sp: 0xFFFE,
+				ram: &[],
+			},
+			1,
+		);
+
+		// SBC A, (HL)
+		run_vector(
+			&State {
+				af: 0x1010,
+				bc: 0,
+				de: 0,
+				hl: 0xC100,
+				pc: 0x0100,
+				sp: 0xFFFE,
+				ram: &[(0x0100, 0x9E), (0xC100, 0x0F)],
+			},
+			&State {
+				af: 0x00E0,
+				bc: 0,
+				de: 0,
+				hl: 0xC100,
+				pc: 0x0101,
+				sp: 0xFFFE,
+				ram: &[],
+			},
+			2,
+		);
+
+		// LD (a16), SP
+		run_vector(
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0xBEEF,
+				ram: &[(0x0100, 0x08), (0x0101, 0x00), (0x0102, 0xC2)],
+			},
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0103,
+				sp: 0xBEEF,
+				ram: &[(0xC200, 0xEF), (0xC201, 0xBE)],
+			},
+			5,
+		);
+
+		// CALL a16
+		run_vector(
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0xD000,
+				ram: &[(0x0100, 0xCD), (0x0101, 0x34), (0x0102, 0x12)],
+			},
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x1234,
+				sp: 0xCFFE,
+				ram: &[(0xCFFF, 0x01), (0xCFFE, 0x03)],
+			},
+			6,
+		);
+
+		// ADD SP, e8
+		run_vector(
+			&State {
+				af: 0x00C0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0x0001,
+				ram: &[(0x0100, 0xE8), (0x0101, 0xFF)],
+			},
+			&State {
+				af: 0x0030,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0102,
+				sp: 0x0000,
+				ram: &[],
+			},
+			4,
+		);
+
+		// DAA
+		run_vector(
+			&State {
+				af: 0x3C00,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0xFFFE,
+				ram: &[(0x0100, 0x27)],
+			},
+			&State {
+				af: 0x4200,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0101,
+				sp: 0xFFFE,
+				ram: &[],
+			},
+			1,
+		);
+
+		// POP AF
+		run_vector(
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0xC000,
+				ram: &[(0x0100, 0xF1), (0xC000, 0xFF), (0xC001, 0x12)],
+			},
+			&State {
+				af: 0x12F0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0101,
+				sp: 0xC002,
+				ram: &[],
+			},
+			3,
+		);
+
+		// SWAP (HL)
+		run_vector(
+			&State {
+				af: 0x0070,
+				bc: 0,
+				de: 0,
+				hl: 0xC000,
+				pc: 0x0100,
+				sp: 0xFFFE,
+				ram: &[(0x0100, 0xCB), (0x0101, 0x36), (0xC000, 0xAB)],
+			},
+			&State {
+				af: 0x0000,
+				bc: 0,
+				de: 0,
+				hl: 0xC000,
+				pc: 0x0102,
+				sp: 0xFFFE,
+				ram: &[(0xC000, 0xBA)],
+			},
+			4,
+		);
+
+		// JR NZ, -2
+		run_vector(
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0xC000,
+				sp: 0xFFFE,
+				ram: &[(0xC000, 0x20), (0xC001, 0xFE)],
+			},
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0xC000,
+				sp: 0xFFFE,
+				ram: &[],
+			},
+			3,
+		);
+
+		// RET C, not taken
+		run_vector(
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0100,
+				sp: 0xFFFE,
+				ram: &[(0x0100, 0xD8)],
+			},
+			&State {
+				af: 0,
+				bc: 0,
+				de: 0,
+				hl: 0,
+				pc: 0x0101,
+				sp: 0xFFFE,
+				ram: &[],
+			},
+			2,
+		);
+	}
+
+	#[test]
+	fn illegal_opcode_lockup() {
+		// INC A; 0xDD; INC A
+		let mut cpu = cpu_with_program(&[0x3C, 0xDD, 0x3C]);
+		cpu.reg.a = 0;
+		cpu.bus.inte = 0x01;
+
+		cpu.do_cycle();
+		assert_eq!(cpu.lockup(), None);
+
+		assert_eq!(cpu.do_cycle(), 4);
+		assert_eq!(
+			cpu.lockup(),
+			Some(Lockup {
+				pc: 0xC001,
+				opcode: 0xDD
+			})
+		);
+
+		cpu.bus.intf = 0x01;
+		for _ in 0..10 {
+			assert_eq!(cpu.do_cycle(), 4);
+		}
+		assert_eq!(cpu.reg.a, 1);
+		assert_eq!(cpu.reg.pc, 0xC002);
+	}
+
+	#[test]
+	fn boot_rom_unmaps() {
+		let mut rom = vec![0; 0x8000];
+		rom[0x0000] = 0xAA;
+		let cart = mbc::get_mbc(rom, true).unwrap();
+
+		// NOPs, then LD A, 0x01; LDH (0x50), A
+		let mut boot_rom = vec![0; 0x100];
+		boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+		let mut cpu = CPU::classic(cart, Model::Dmg, Some(boot_rom), None).unwrap();
+
+		assert_eq!(cpu.reg.pc, 0x0000);
+		assert!(!cpu.bus.gpu.lcd_on());
+		assert_eq!(cpu.bus.rb(0x0000), 0x00);
+
+		while cpu.reg.pc < 0x0100 {
+			cpu.do_cycle();
+		}
+
+		assert!(!cpu.bus.boot_rom_mapped());
+		assert_eq!(cpu.bus.rb(0x0000), 0xAA);
+	}
+
+	#[test]
+	fn boot_rom_size() {
+		let cart = mbc::get_mbc(vec![0; 0x8000], true).unwrap();
+		assert!(CPU::cgb(cart, Model::Cgb, Some(vec![0; 0x100]), None).is_err());
+	}
+
+	#[test]
+	fn halt_bug() {
+		// HALT; INC A; NOP
+		let mut cpu = cpu_with_program(&[0x76, 0x3C, 0x00]);
+		cpu.reg.a = 0;
+		cpu.bus.inte = 0x01;
+		cpu.bus.intf = 0x01;
+
+		cpu.do_cycle();
+		assert!(!cpu.halted);
+		assert_eq!(cpu.reg.pc, 0xC001);
+
+		cpu.do_cycle();
+		assert_eq!(cpu.reg.a, 1);
+		assert_eq!(cpu.reg.pc, 0xC001);
+
+		cpu.do_cycle();
+		assert_eq!(cpu.reg.a, 2);
+		assert_eq!(cpu.reg.pc, 0xC002);
+	}
+
+	#[test]
+	fn halt_bug_after_ei() {
+		// EI; HALT
+		let mut cpu = cpu_with_program(&[0xFB, 0x76]);
+		cpu.reg.sp = 0xD000;
+		cpu.bus.inte = 0x01;
+		cpu.bus.intf = 0x01;
+
+		cpu.do_cycle();
+		cpu.do_cycle();
+		cpu.do_cycle();
+		assert_eq!(cpu.reg.pc, 0x0040);
+		assert_eq!(cpu.bus.rb(0xCFFF), 0xC0);
+		assert_eq!(cpu.bus.rb(0xCFFE), 0x01);
+		assert!(!cpu.halt_bug);
+	}
+
+	#[test]
+	fn stop_waits_for_button() {
+		// STOP; INC A
+		let mut cpu = cpu_with_program(&[0x10, 0x00, 0x3C]);
+		cpu.reg.a = 0;
+		cpu.bus.keypad.wb(0x20);
+
+		cpu.do_cycle();
+		assert!(cpu.stopped);
+		assert_eq!(cpu.bus.timer.rb(0xFF04), 0);
+
+		// Neither the timer nor the PPU run while stopped.
+		let div = cpu.bus.timer.rb(0xFF04);
+		let ly = cpu.bus.gpu.rb(0xFF44);
+		for _ in 0..2000 {
+			cpu.do_cycle();
+		}
+		assert_eq!(cpu.reg.a, 0);
+		assert_eq!(cpu.bus.timer.rb(0xFF04), div);
+		assert_eq!(cpu.bus.gpu.rb(0xFF44), ly);
+
+		cpu.bus.keypad.keydown(KeypadKey::Right);
+		cpu.do_cycle();
+		assert!(!cpu.stopped);
+		assert_eq!(cpu.reg.a, 1);
+	}
+
+	#[test]
+	fn stop_switches_speed() {
+		// STOP; INC A
+		let mut cpu = cgb_with_program(&[0x10, 0x00, 0x3C]);
+		cpu.reg.a = 0;
+		cpu.bus.wb(0xFF4D, 0x01);
+		assert_eq!(cpu.bus.rb(0xFF4D), 0x7F);
+
+		cpu.do_cycle();
+		assert!(!cpu.stopped);
+		assert_eq!(cpu.bus.rb(0xFF4D), 0xFE);
+
+		assert_eq!(cpu.do_cycle(), 2);
+		assert_eq!(cpu.reg.a, 1);
+	}
+
+	#[test]
+	fn memory_access_timing() {
+		// DIV increments every 64 M-cycles. After 60 NOPs, the read in the
+		// fourth cycle of `LD A, (0xFF04)` lands exactly on that increment.
+		let mut program = vec![0x00; 60];
+		program.extend_from_slice(&[0xFA, 0x04, 0xFF]);
+		let mut cpu = cpu_with_program(&program);
+		let div = cpu.bus.timer.rb(0xFF04);
+
+		for _ in 0..60 {
+			assert_eq!(cpu.do_cycle(), 4);
+		}
+
+		assert_eq!(cpu.do_cycle(), 16);
+		assert_eq!(cpu.reg.a, div.wrapping_add(1));
+	}
+
+	#[test]
+	fn instruction_ticks() {
+		// PUSH BC; CALL 0xC010; ... ; RET NZ
+		let mut program = vec![0x00; 0x11];
+		program[0..4].copy_from_slice(&[0xC5, 0xCD, 0x10, 0xC0]);
+		program[0x10] = 0xC0;
+		let mut cpu = cpu_with_program(&program);
+		cpu.reg.sp = 0xD000;
+		cpu.reg.flag(CpuFlags::Z, false);
+
+		assert_eq!(cpu.do_cycle(), 16);
+		assert_eq!(cpu.do_cycle(), 24);
+		assert_eq!(cpu.reg.pc, 0xC010);
+		assert_eq!(cpu.do_cycle(), 20);
+		assert_eq!(cpu.reg.pc, 0xC004);
+		assert_eq!(cpu.reg.sp, 0xCFFE);
+	}
+
+	#[test]
+	fn interrupt_dispatch() {
+		let mut cpu = cpu_with_program(&[0x00]);
+		cpu.ime = true;
+		cpu.reg.sp = 0xD000;
+		cpu.bus.inte = 0x05;
+		cpu.bus.intf = 0x04;
+
+		assert_eq!(cpu.do_cycle(), 20);
+		assert_eq!(cpu.reg.pc, 0x0050);
+		assert_eq!(cpu.bus.intf & 0x1F, 0x00);
+		assert_eq!(cpu.bus.rb(0xCFFF), 0xC0);
+		assert_eq!(cpu.bus.rb(0xCFFE), 0x00);
+		assert!(!cpu.ime);
+	}
+
+	#[test]
+	fn interrupt_dispatch_cancelled_by_ie_push() {
+		let mut cpu = cpu_with_program(&[0x00]);
+		cpu.ime = true;
+		cpu.reg.sp = 0x0000;
+		cpu.bus.inte = 0x01;
+		cpu.bus.intf = 0x01;
+
+		assert_eq!(cpu.do_cycle(), 20);
+		assert_eq!(cpu.reg.pc, 0x0000);
+		assert_eq!(cpu.reg.sp, 0xFFFE);
+		assert_eq!(cpu.bus.inte, 0xC0);
+		assert_eq!(cpu.bus.intf & 0x1F, 0x01);
+	}
+
+	#[test]
+	fn cb_rotates() {
+		// RLC B; RR B
+		let mut cpu = cpu_with_program(&[0xCB, 0x00, 0xCB, 0x18]);
+		cpu.reg.b = 0x85;
+		cpu.reg.flag(CpuFlags::C, false);
+
+		assert_eq!(cpu.call(), 2);
+		assert_eq!(cpu.reg.b, 0x0B);
+		assert!(cpu.reg.get_flag(CpuFlags::C));
+		assert!(!cpu.reg.get_flag(CpuFlags::Z));
+
+		assert_eq!(cpu.call(), 2);
+		assert_eq!(cpu.reg.b, 0x85);
+		assert!(cpu.reg.get_flag(CpuFlags::C));
+		assert!(!cpu.reg.get_flag(CpuFlags::H));
+		assert!(!cpu.reg.get_flag(CpuFlags::N));
+	}
+
+	#[test]
+	fn cb_shifts() {
+		// SLA A; SRA A; SRL A
+		let mut cpu = cpu_with_program(&[0xCB, 0x27, 0xCB, 0x2F, 0xCB, 0x3F]);
+		cpu.reg.a = 0x80;
+
+		cpu.call();
+		assert_eq!(cpu.reg.a, 0x00);
+		assert!(cpu.reg.get_flag(CpuFlags::Z));
+		assert!(cpu.reg.get_flag(CpuFlags::C));
+
+		cpu.reg.a = 0x81;
+		cpu.call();
+		assert_eq!(cpu.reg.a, 0xC0);
+		assert!(!cpu.reg.get_flag(CpuFlags::Z));
+		assert!(cpu.reg.get_flag(CpuFlags::C));
+
+		cpu.call();
+		assert_eq!(cpu.reg.a, 0x60);
+		assert!(!cpu.reg.get_flag(CpuFlags::C));
+	}
+
+	#[test]
+	fn cb_swap() {
+		// SWAP C
+		let mut cpu = cpu_with_program(&[0xCB, 0x31, 0xCB, 0x31]);
+		cpu.reg.c = 0xF1;
+		cpu.reg.flag(CpuFlags::C | CpuFlags::H | CpuFlags::N, true);
+
+		cpu.call();
+		assert_eq!(cpu.reg.c, 0x1F);
+		assert!(!cpu.reg.get_flag(CpuFlags::Z));
+		assert!(!cpu.reg.get_flag(CpuFlags::C));
+		assert!(!cpu.reg.get_flag(CpuFlags::H));
+		assert!(!cpu.reg.get_flag(CpuFlags::N));
+
+		cpu.reg.c = 0x00;
+		cpu.call();
+		assert!(cpu.reg.get_flag(CpuFlags::Z));
+	}
+
+	#[test]
+	fn cb_bit() {
+		// BIT 7, H; BIT 0, H
+		let mut cpu = cpu_with_program(&[0xCB, 0x7C, 0xCB, 0x44]);
+		cpu.reg.h = 0x80;
+		cpu.reg.flag(CpuFlags::C, true);
+		cpu.reg.flag(CpuFlags::N, true);
+
+		assert_eq!(cpu.call(), 2);
+		assert!(!cpu.reg.get_flag(CpuFlags::Z));
+		assert!(cpu.reg.get_flag(CpuFlags::H));
+		assert!(!cpu.reg.get_flag(CpuFlags::N));
+		assert!(cpu.reg.get_flag(CpuFlags::C));
+
+		cpu.call();
+		assert!(cpu.reg.get_flag(CpuFlags::Z));
+		assert!(cpu.reg.get_flag(CpuFlags::C));
+		assert_eq!(cpu.reg.h, 0x80);
+	}
+
+	#[test]
+	fn cb_hl_operand() {
+		// BIT 3, (HL); SET 3, (HL); RES 7, (HL); RLC (HL)
+		let mut cpu = cpu_with_program(&[0xCB, 0x5E, 0xCB, 0xDE, 0xCB, 0xBE, 0xCB, 0x06]);
+		cpu.reg.set_hl(0xD000);
+		cpu.bus.wb(0xD000, 0x80);
+
+		assert_eq!(cpu.call(), 3);
+		assert!(cpu.reg.get_flag(CpuFlags::Z));
+
+		assert_eq!(cpu.call(), 4);
+		assert_eq!(cpu.bus.rb(0xD000), 0x88);
+
+		assert_eq!(cpu.call(), 4);
+		assert_eq!(cpu.bus.rb(0xD000), 0x08);
+
+		assert_eq!(cpu.call(), 4);
+		assert_eq!(cpu.bus.rb(0xD000), 0x10);
+		assert!(!cpu.reg.get_flag(CpuFlags::C));
+		assert!(!cpu.reg.get_flag(CpuFlags::Z));
 	}
 }
@@ -1,22 +1,465 @@
+use std::{path::PathBuf, time::Duration};
+
 use serde::{Deserialize, Serialize};
 
 use super::{
 	StrResult,
-	cpu::CPU,
-	gb_mode::GbMode,
+	compat_palettes::{CompatPalette, Palettes},
+	cpu::{CPU, Lockup},
+	gb_mode::{GbMode, Model},
+	gpu::Renderer,
+	infrared::InfraredCallback,
 	keypad::KeypadKey,
 	mbc,
 	printer::GbPrinter,
-	serial::{self, SerialCallback},
-	sound,
+	serial::SerialCallback,
+	sound::AudioPlayer,
 };
 
+/// The base clock rate of the Game Boy, which all cycle counts are in.
+pub const CLOCK_SPEED: u32 = 4_194_304;
+
+/// The number of clock cycles in a single frame while the LCD is on.
+pub const CYCLES_PER_FRAME: u32 = 70_224;
+
 #[derive(Serialize, Deserialize)]
 pub struct Device {
 	cpu: CPU,
 	save_state: Option<String>,
+	cycles: u64,
+	cycle_debt: u32,
+}
+
+impl Device {
+	/// Creates a classic Game Boy running the given ROM image.
+	pub fn classic(rom: Vec<u8>, skip_checksum: bool) -> StrResult<Self> {
+		DeviceBuilder::new()
+			.model(Model::Dmg)
+			.skip_checksum(skip_checksum)
+			.build(rom)
+	}
+
+	/// Creates a Game Boy Color running the given ROM image.
+	pub fn cgb(rom: Vec<u8>, skip_checksum: bool) -> StrResult<Self> {
+		DeviceBuilder::new()
+			.model(Model::Cgb)
+			.skip_checksum(skip_checksum)
+			.build(rom)
+	}
+
+	/// Creates a classic Game Boy running the ROM at `path`, as with
+	/// [`DeviceBuilder::build_from_path`].
+	pub fn classic_from_path(path: impl Into<PathBuf>, skip_checksum: bool) -> StrResult<Self> {
+		DeviceBuilder::new()
+			.model(Model::Dmg)
+			.skip_checksum(skip_checksum)
+			.build_from_path(path)
+	}
+
+	/// Creates a Game Boy Color running the ROM at `path`, as with
+	/// [`DeviceBuilder::build_from_path`].
+	pub fn cgb_from_path(path: impl Into<PathBuf>, skip_checksum: bool) -> StrResult<Self> {
+		DeviceBuilder::new()
+			.model(Model::Cgb)
+			.skip_checksum(skip_checksum)
+			.build_from_path(path)
+	}
+
+	const fn new(cpu: CPU) -> Self {
+		Self {
+			cpu,
+			save_state: None,
+			cycles: 0,
+			cycle_debt: 0,
+		}
+	}
+
+	/// Runs a single instruction, returning the number of clock cycles that
+	/// passed at normal speed.
+	pub fn do_tick(&mut self) -> u32 {
+		let ticks = self.cpu.do_cycle();
+		self.cycles += u64::from(ticks);
+		ticks
+	}
+
+	/// Runs until the next vertical blank and returns the number of clock
+	/// cycles that took. While the LCD is off, or STOP has halted the clock,
+	/// a frame's worth of cycles is run instead.
+	pub fn run_frame(&mut self) -> u32 {
+		let frame = self.frames();
+		let mut cycles = 0;
+		while self.frames() == frame {
+			cycles += self.do_tick();
+			if !self.drawing() && cycles >= CYCLES_PER_FRAME {
+				break;
+			}
+		}
+
+		cycles
+	}
+
+	/// Runs for at least `cycles` clock cycles and returns how many actually
+	/// ran. Cycles run past the target are made up for on the next call, so
+	/// repeated calls do not drift.
+	pub fn run_cycles(&mut self, cycles: u32) -> u32 {
+		let target = cycles.saturating_sub(self.cycle_debt);
+		self.cycle_debt = self.cycle_debt.saturating_sub(cycles);
+
+		let mut ran = 0;
+		while ran < target {
+			ran += self.do_tick();
+		}
+
+		self.cycle_debt += ran - target;
+		ran
+	}
+
+	/// Runs for `duration` of emulated time.
+	pub fn run_for(&mut self, duration: Duration) -> u32 {
+		let cycles = duration.as_nanos() * u128::from(CLOCK_SPEED) / 1_000_000_000;
+		self.run_cycles(cycles.try_into().unwrap_or(u32::MAX))
+	}
+
+	/// The total number of clock cycles run so far.
+	#[must_use]
+	pub const fn cycles(&self) -> u64 {
+		self.cycles
+	}
+
+	/// The total number of frames drawn so far.
+	#[must_use]
+	pub const fn frames(&self) -> u64 {
+		self.cpu.bus.gpu.frames
+	}
+
+	/// Whether a new frame was drawn since the last call.
+	pub const fn check_and_reset_gpu_updated(&mut self) -> bool {
+		let result = self.cpu.bus.gpu.updated;
+		self.cpu.bus.gpu.updated = false;
+		result
+	}
+
+	/// The current frame as RGB bytes, [`SCREEN_W`](crate::SCREEN_W) by
+	/// [`SCREEN_H`](crate::SCREEN_H) pixels. On a Super Game Boy, this is
+	/// colorized with the palettes the game picked.
+	#[must_use]
+	pub fn gpu_data(&self) -> &[u8] {
+		match &self.cpu.bus.sgb {
+			Some(sgb) => &sgb.data,
+			None => &self.cpu.bus.gpu.data,
+		}
+	}
+
+	/// The current frame including the Super Game Boy border as RGB bytes,
+	/// [`SGB_SCREEN_W`](crate::SGB_SCREEN_W) by
+	/// [`SGB_SCREEN_H`](crate::SGB_SCREEN_H) pixels. Only available when
+	/// enabled with [`DeviceBuilder::sgb_border`] for a game with SGB support.
+	#[must_use]
+	pub fn sgb_border_data(&self) -> Option<&[u8]> {
+		self.cpu.bus.sgb.as_ref()?.border_data.as_deref()
+	}
+
+	pub const fn keydown(&mut self, key: KeypadKey) {
+		self.cpu.bus.keypad.keydown(key);
+	}
+
+	pub const fn keyup(&mut self, key: KeypadKey) {
+		self.cpu.bus.keypad.keyup(key);
+	}
+
+	pub fn enable_audio(&mut self, player: Box<dyn AudioPlayer>) {
+		self.cpu.bus.enable_sound(player);
+	}
+
+	/// Drops buffered audio until the player runs out, to catch up after the
+	/// emulator was paused or slowed down.
+	pub const fn sync_audio(&mut self) {
+		if let Some(sound) = self.cpu.bus.sound.as_mut() {
+			sound.sync();
+		}
+	}
+
+	pub fn set_serial_callback(&mut self, cb: Box<dyn SerialCallback>) {
+		self.cpu.bus.serial.set_callback(cb);
+	}
+
+	pub fn clear_serial_callback(&mut self) {
+		self.cpu.bus.serial.clear_callback();
+	}
+
+	/// Connects something to the infrared port. Only games in color mode can
+	/// use it.
+	pub fn set_infrared_callback(&mut self, cb: Box<dyn InfraredCallback>) {
+		self.cpu.bus.infrared.set_callback(cb);
+	}
+
+	pub fn clear_infrared_callback(&mut self) {
+		self.cpu.bus.infrared.clear_callback();
+	}
+
+	/// Connects a Game Boy Printer to the serial port.
+	pub fn attach_printer(&mut self) {
+		self.set_serial_callback(Box::new(GbPrinter::new()));
+	}
+
+	#[must_use]
+	pub fn rom_name(&self) -> String {
+		self.cpu.bus.mbc.rom_name()
+	}
+
+	#[must_use]
+	pub const fn model(&self) -> Model {
+		self.cpu.bus.model
+	}
+
+	/// Whether frames are being drawn, which they aren't while the LCD is off
+	/// or STOP has halted the clock.
+	pub(crate) const fn drawing(&self) -> bool {
+		self.cpu.bus.gpu.lcd_on() && !self.cpu.stopped()
+	}
+
+	/// The illegal opcode the CPU locked up on, if any.
+	#[must_use]
+	pub const fn lockup(&self) -> Option<Lockup> {
+		self.cpu.lockup()
+	}
+
+	#[must_use]
+	pub fn is_battery_backed(&self) -> bool {
+		self.cpu.bus.mbc.is_battery_backed()
+	}
+
+	/// Whether cartridge RAM was written to since the last call.
+	pub fn check_and_reset_ram_updated(&mut self) -> bool {
+		self.cpu.bus.mbc.check_and_reset_ram_updated()
+	}
+
+	#[must_use]
+	pub fn dump_ram(&self) -> Vec<u8> {
+		self.cpu.bus.mbc.dump_ram()
+	}
+
+	pub fn load_ram(&mut self, ram_data: &[u8]) -> StrResult<()> {
+		self.cpu.bus.mbc.load_ram(ram_data)
+	}
+}
+
+/// Configures the hardware a [`Device`] emulates.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceBuilder {
+	model: Option<Model>,
+	boot_rom: Option<Vec<u8>>,
+	compat_palette: Option<CompatPalette>,
+	sgb_border: bool,
+	renderer: Renderer,
+	restrict_ppu_access: bool,
+	skip_checksum: bool,
+}
+
+impl DeviceBuilder {
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			model: None,
+			boot_rom: None,
+			compat_palette: None,
+			sgb_border: false,
+			renderer: Renderer::Scanline,
+			restrict_ppu_access: true,
+			skip_checksum: false,
+		}
+	}
+
+	/// Selects the hardware model. When unset, it is detected from the CGB
+	/// and SGB flags in the cartridge header.
+	#[must_use]
+	pub const fn model(mut self, model: Model) -> Self {
+		self.model = Some(model);
+		self
+	}
+
+	/// Runs the given boot ROM image before the cartridge, instead of
+	/// starting from the state it leaves behind. It has to be 256 bytes for
+	/// the DMG family, and 2304 bytes for the CGB and AGB.
+	#[must_use]
+	pub fn boot_rom(mut self, boot_rom: Vec<u8>) -> Self {
+		self.boot_rom = Some(boot_rom);
+		self
+	}
+
+	/// Colorizes classic games on a Game Boy Color with the given palette,
+	/// instead of the one picked from the cartridge title. This has no effect
+	/// when a boot ROM is used, as it picks the palette itself.
+	#[must_use]
+	pub const fn compat_palette(mut self, palette: CompatPalette) -> Self {
+		self.compat_palette = Some(palette);
+		self
+	}
+
+	/// Renders the Super Game Boy border around the screen, available from
+	/// [`Device::sgb_border_data`].
+	#[must_use]
+	pub const fn sgb_border(mut self, sgb_border: bool) -> Self {
+		self.sgb_border = sgb_border;
+		self
+	}
+
+	/// Selects how the picture is drawn. [`Renderer::Fifo`] is slower, but
+	/// shows effects that change registers partway through a line.
+	#[must_use]
+	pub const fn renderer(mut self, renderer: Renderer) -> Self {
+		self.renderer = renderer;
+		self
+	}
+
+	/// Whether the CPU is locked out of VRAM, OAM and palette RAM while the
+	/// PPU is using them, like on hardware. On by default.
+	#[must_use]
+	pub const fn restrict_ppu_access(mut self, restrict_ppu_access: bool) -> Self {
+		self.restrict_ppu_access = restrict_ppu_access;
+		self
+	}
+
+	/// Accepts cartridges with an invalid header checksum.
+	#[must_use]
+	pub const fn skip_checksum(mut self, skip_checksum: bool) -> Self {
+		self.skip_checksum = skip_checksum;
+		self
+	}
+
+	pub fn build(self, rom: Vec<u8>) -> StrResult<Device> {
+		let cart = mbc::get_mbc(rom, self.skip_checksum)?;
+		self.build_with_cart(cart)
+	}
+
+	/// Builds a device running the ROM at `path`. Battery-backed cartridge
+	/// RAM is loaded from, and saved to, a `.gbsave` file next to it.
+	pub fn build_from_path(self, path: impl Into<PathBuf>) -> StrResult<Device> {
+		let cart = mbc::FileBackedMBC::new(path.into(), self.skip_checksum)?;
+		self.build_with_cart(Box::new(cart))
+	}
+
+	fn build_with_cart(self, cart: Box<dyn mbc::MBC + 'static>) -> StrResult<Device> {
+		let model = self.model.unwrap_or_else(|| {
+			Model::detect(
+				cart.read_rom(0x0143),
+				cart.read_rom(0x0146),
+				cart.read_rom(0x014B),
+			)
+		});
+
+		let mut cpu = if model.is_cgb() {
+			CPU::cgb(cart, model, self.boot_rom, None)?
+		} else {
+			CPU::classic(cart, model, self.boot_rom, None)?
+		};
+
+		if let Some(palette) = self.compat_palette
+			&& cpu.bus.gbmode == GbMode::ColorAsClassic
+			&& !cpu.bus.boot_rom_mapped()
+		{
+			cpu.bus.gpu.load_compat_palettes(Palettes::manual(palette));
+		}
+
+		cpu.bus.gpu.set_renderer(self.renderer);
+		cpu.bus.gpu.set_restrict_access(self.restrict_ppu_access);
+
+		if self.sgb_border
+			&& let Some(sgb) = cpu.bus.sgb.as_mut()
+		{
+			sgb.enable_border();
+		}
+
+		Ok(Device::new(cpu))
+	}
 }
 
 impl Drop for Device {
 	fn drop(&mut self) {}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::{CLOCK_SPEED, CYCLES_PER_FRAME, Device, DeviceBuilder};
+	use crate::{Model, SCREEN_H, SCREEN_W};
+
+	fn rom_with_title(title: &[u8]) -> Vec<u8> {
+		let mut rom = vec![0; 0x8000];
+		rom[0x134..0x134 + title.len()].copy_from_slice(title);
+		rom
+	}
+
+	#[test]
+	fn runs_rom_from_buffer() {
+		let mut device = Device::classic(rom_with_title(b"CRANEBOY"), true).unwrap();
+		assert_eq!(device.rom_name(), "CRANEBOY");
+		assert_eq!(device.gpu_data().len(), SCREEN_W * SCREEN_H * 3);
+
+		let mut cycles = 0;
+		while !device.check_and_reset_gpu_updated() {
+			cycles += device.do_tick();
+			assert!(cycles <= 70224, "no frame was drawn");
+		}
+
+		assert_eq!(device.lockup(), None);
+	}
+
+	#[test]
+	fn run_frame() {
+		let mut device = Device::classic(rom_with_title(b"CRANEBOY"), true).unwrap();
+		device.run_frame();
+		assert_eq!(device.frames(), 1);
+
+		for frame in 2..5 {
+			let cycles = device.run_frame();
+			assert!(cycles.abs_diff(CYCLES_PER_FRAME) < 24);
+			assert_eq!(device.frames(), frame);
+		}
+	}
+
+	#[test]
+	fn run_for_duration() {
+		let mut device = Device::classic(rom_with_title(b"CRANEBOY"), true).unwrap();
+		for _ in 0..60 {
+			device.run_for(Duration::from_secs(1) / 60);
+		}
+
+		let expected = u64::from(CLOCK_SPEED) / 60 * 60;
+		assert!(device.cycles().abs_diff(expected) < 24);
+		assert_eq!(device.frames(), 59);
+	}
+
+	#[test]
+	fn model_detection() {
+		let mut rom = rom_with_title(b"CRANEBOY");
+		let device = DeviceBuilder::new()
+			.skip_checksum(true)
+			.build(rom.clone())
+			.unwrap();
+		assert_eq!(device.model(), Model::Dmg);
+
+		rom[0x146] = 0x03;
+		rom[0x14B] = 0x33;
+		let device = DeviceBuilder::new()
+			.skip_checksum(true)
+			.build(rom.clone())
+			.unwrap();
+		assert_eq!(device.model(), Model::Sgb);
+
+		rom[0x143] = 0x80;
+		let device = DeviceBuilder::new()
+			.skip_checksum(true)
+			.build(rom.clone())
+			.unwrap();
+		assert_eq!(device.model(), Model::Cgb);
+
+		let device = DeviceBuilder::new()
+			.model(Model::Agb)
+			.skip_checksum(true)
+			.build(rom)
+			.unwrap();
+		assert_eq!(device.model(), Model::Agb);
+	}
+}
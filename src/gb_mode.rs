@@ -12,3 +12,47 @@ pub enum GbSpeed {
 	Single = 1,
 	Double,
 }
+
+/// The specific Game Boy hardware being emulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Model {
+	/// The original Game Boy with the early revision boot ROM.
+	Dmg0,
+	Dmg,
+	/// Game Boy Pocket and Game Boy Light.
+	Mgb,
+	/// Super Game Boy.
+	Sgb,
+	Sgb2,
+	/// Game Boy Color.
+	Cgb,
+	/// Game Boy Advance, running Game Boy software.
+	Agb,
+}
+
+impl Model {
+	/// Picks a model from the cartridge header: a Game Boy Color for games
+	/// with CGB support, a Super Game Boy for games with SGB support, and a
+	/// plain Game Boy for everything else.
+	#[must_use]
+	pub const fn detect(cgb_flag: u8, sgb_flag: u8, old_licensee: u8) -> Self {
+		if matches!(cgb_flag & 0x80, 0x80) {
+			Self::Cgb
+		} else if matches!(sgb_flag, 0x03) && matches!(old_licensee, 0x33) {
+			Self::Sgb
+		} else {
+			Self::Dmg
+		}
+	}
+
+	/// Whether this model is capable of running Game Boy Color software.
+	#[must_use]
+	pub const fn is_cgb(self) -> bool {
+		matches!(self, Self::Cgb | Self::Agb)
+	}
+
+	#[must_use]
+	pub const fn is_sgb(self) -> bool {
+		matches!(self, Self::Sgb | Self::Sgb2)
+	}
+}
@@ -1,8 +1,11 @@
+mod fifo;
+
 use std::cmp::Ordering;
 
 use serde::{Deserialize, Serialize};
 
-use super::gb_mode::GbMode;
+use self::fifo::Fifo;
+use super::{compat_palettes::Palettes, gb_mode::GbMode};
 
 const VRAM_SIZE: usize = 0x4000;
 const VOAM_SIZE: usize = 0xA0;
@@ -13,6 +16,8 @@ pub const SCREEN_H: usize = 144;
 pub struct GPU {
 	mode: u8,
 	modeclock: u32,
+	mode3_len: u32,
+	stat_line: bool,
 	line: u8,
 	lyc: u8,
 	lcd_on: bool,
@@ -49,15 +54,33 @@ pub struct GPU {
 	csprit_inc: bool,
 	csprit_ind: u8,
 	csprit: [[[u8; 3]; 4]; 8],
+	coordinate_priority: bool,
 	vrambank: usize,
 	pub data: Vec<u8>,
+	pub shades: Vec<u8>,
 	#[serde(with = "serde_arrays")]
 	bgprio: [PrioType; SCREEN_W],
 	pub updated: bool,
+	pub frames: u64,
 	pub interrupt: u8,
 	pub gbmode: GbMode,
 	hblanking: bool,
 	first_frame: bool,
+	renderer: Renderer,
+	fifo: Fifo,
+	restrict_access: bool,
+}
+
+/// How the PPU turns VRAM into pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Renderer {
+	/// Draws each line in one go once it is done. This is fast, but misses
+	/// changes made to the registers partway through a line.
+	#[default]
+	Scanline,
+	/// Feeds pixels through the background and sprite FIFOs a dot at a time,
+	/// like the hardware does.
+	Fifo,
 }
 
 impl GPU {
@@ -65,6 +88,8 @@ impl GPU {
 		Self {
 			mode: 0,
 			modeclock: 0,
+			mode3_len: 172,
+			stat_line: false,
 			line: 0,
 			lyc: 0,
 			lcd_on: false,
@@ -94,8 +119,10 @@ impl GPU {
 			vram: [0; VRAM_SIZE],
 			voam: [0; VOAM_SIZE],
 			data: vec![0; SCREEN_W * SCREEN_H * 3],
+			shades: vec![0; SCREEN_W * SCREEN_H],
 			bgprio: [PrioType::Normal; SCREEN_W],
 			updated: false,
+			frames: 0,
 			interrupt: 0,
 			gbmode: GbMode::Classic,
 			cbgpal_inc: false,
@@ -104,9 +131,13 @@ impl GPU {
 			csprit_inc: false,
 			csprit_ind: 0,
 			csprit: [[[0u8; 3]; 4]; 8],
+			coordinate_priority: false,
 			vrambank: 0,
 			hblanking: false,
 			first_frame: false,
+			renderer: Renderer::Scanline,
+			fifo: Fifo::default(),
+			restrict_access: true,
 		}
 	}
 
@@ -114,6 +145,26 @@ impl GPU {
 		Self::classic()
 	}
 
+	pub const fn set_renderer(&mut self, renderer: Renderer) {
+		self.renderer = renderer;
+	}
+
+	/// Whether the CPU is kept out of VRAM, OAM and palette RAM while the PPU
+	/// is using them. Debugging tools can turn this off to see everything.
+	pub const fn set_restrict_access(&mut self, restrict_access: bool) {
+		self.restrict_access = restrict_access;
+	}
+
+	/// The PPU reads VRAM and palette RAM while drawing, which is mode 3.
+	const fn vram_accessible(&self) -> bool {
+		!self.restrict_access || !self.lcd_on || self.mode != 3
+	}
+
+	/// The PPU reads OAM while scanning for sprites and while drawing.
+	const fn oam_accessible(&self) -> bool {
+		!self.restrict_access || !self.lcd_on || self.mode < 2
+	}
+
 	pub fn do_cycle(&mut self, ticks: u32) {
 		if !self.lcd_on {
 			return;
@@ -121,6 +172,14 @@ impl GPU {
 
 		self.hblanking = false;
 
+		if matches!(self.renderer, Renderer::Fifo) {
+			for _ in 0..ticks {
+				self.fifo_dot();
+			}
+
+			return;
+		}
+
 		let mut ticksleft = ticks;
 
 		while ticksleft > 0 {
@@ -131,68 +190,145 @@ impl GPU {
 			if self.modeclock >= 456 {
 				self.modeclock -= 456;
 				self.line = (self.line + 1) % 154;
-				self.check_interrupt_lyc();
+				self.update_stat_line();
 
 				if self.line >= 144 && !matches!(self.mode, 1) {
 					self.change_mode(1);
 				}
 			}
 
+			if self.line == 153 {
+				self.update_stat_line();
+			}
+
 			if self.line < 144 {
 				if self.modeclock <= 80 {
 					if !matches!(self.mode, 2) {
 						self.change_mode(2);
 					}
-				} else if self.modeclock <= (80 + 172) {
-					if !matches!(self.mode, 3) {
+				} else {
+					if matches!(self.mode, 2) {
 						self.change_mode(3);
 					}
-				} else if !matches!(self.mode, 0) {
-					self.change_mode(0);
+
+					if matches!(self.mode, 3) && self.modeclock > 80 + self.mode3_len {
+						self.change_mode(0);
+					}
 				}
 			}
 		}
 	}
 
-	const fn check_interrupt_lyc(&mut self) {
-		if self.lyc_inte && self.line == self.lyc {
+	/// LY as the CPU sees it. On line 153 it only reads 153 for the first few
+	/// dots, and 0 for the rest of the line.
+	const fn ly(&self) -> u8 {
+		if self.line == 153 && self.modeclock >= 4 {
+			0
+		} else {
+			self.line
+		}
+	}
+
+	/// Requests a STAT interrupt when the enabled sources go from all being
+	/// inactive to at least one being active. They share a single line, so a
+	/// source becoming active while another already is goes unnoticed.
+	const fn update_stat_line(&mut self) {
+		let active = self.lcd_on
+			&& ((self.lyc_inte && self.ly() == self.lyc)
+				|| match self.mode {
+					0 => self.m0_inte,
+					1 => self.m1_inte,
+					2 => self.m2_inte,
+					_ => false,
+				});
+
+		if active && !self.stat_line {
 			self.interrupt |= 0x02;
 		}
+
+		self.stat_line = active;
+	}
+
+	/// How many dots mode 3 lasts on the current line. On top of the 172 it
+	/// takes at least, the fine scroll is discarded a pixel per dot, the
+	/// window restarts the fetcher, and every sprite stalls it for 6 dots plus
+	/// however long the background fetch it interrupts has left to go.
+	fn mode3_length(&self) -> u32 {
+		let fine_scroll = u32::from(self.scx & 0x07);
+		let mut length = 172 + fine_scroll;
+
+		if self.win_on && self.wy_trigger && self.winx <= 166 {
+			length += 6;
+		}
+
+		if !self.sprite_on {
+			return length;
+		}
+
+		let line = i32::from(self.line);
+		let mut sprites = self
+			.voam
+			.chunks_exact(4)
+			.filter(|sprite| {
+				let top = i32::from(sprite[0]) - 16;
+				line >= top && line < top + self.sprite_size as i32
+			})
+			.take(10)
+			.map(|sprite| u32::from(sprite[1]))
+			.filter(|&x| x < 168)
+			.collect::<Vec<_>>();
+		sprites.sort_unstable();
+
+		let mut fetched_tile = None;
+		for x in sprites {
+			let position = x + fine_scroll;
+			if fetched_tile != Some(position / 8) {
+				fetched_tile = Some(position / 8);
+				length += 5u32.saturating_sub(position % 8);
+			}
+
+			length += 6;
+		}
+
+		length
 	}
 
 	fn change_mode(&mut self, mode: u8) {
 		self.mode = mode;
 
-		if match self.mode {
+		match self.mode {
 			0 => {
-				self.render_scan();
+				if matches!(self.renderer, Renderer::Scanline) {
+					self.render_scan();
+				}
+
 				self.hblanking = true;
-				self.m0_inte
 			}
 			1 => {
 				self.wy_trigger = false;
 				self.interrupt |= 0x01;
 				self.updated = true;
+				self.frames += 1;
 				self.first_frame = false;
-				self.m1_inte
 			}
-			2 => self.m2_inte,
 			3 => {
 				if self.win_on && !self.wy_trigger && self.line == self.winy {
 					self.wy_trigger = true;
 					self.wy_pos = -1;
 				}
 
-				false
+				self.mode3_len = self.mode3_length();
 			}
-			_ => false,
-		} {
-			self.interrupt |= 0x02;
+			_ => {}
 		}
+
+		self.update_stat_line();
 	}
 
 	pub fn rb(&self, a: u16) -> u8 {
 		match a {
+			0x8000..=0x9FFF if !self.vram_accessible() => 0xFF,
+			0xFE00..=0xFE9F if !self.oam_accessible() => 0xFF,
 			0x8000..=0x9FFF => self.vram[(self.vrambank * 0x2000) | (a as usize & 0x1FFF)],
 			0xFE00..=0xFE9F => self.voam[a as usize - 0xFE00],
 			0xFF40 => {
@@ -222,12 +358,12 @@ impl GPU {
 					| (if self.m2_inte { 0x20 } else { 0 })
 					| (if self.m1_inte { 0x10 } else { 0 })
 					| (if self.m0_inte { 0x08 } else { 0 })
-					| (if self.line == self.lyc { 0x04 } else { 0 })
+					| (if self.ly() == self.lyc { 0x04 } else { 0 })
 					| self.mode
 			}
 			0xFF42 => self.scy,
 			0xFF43 => self.scx,
-			0xFF44 => self.line,
+			0xFF44 => self.ly(),
 			0xFF45 => self.lyc,
 			0xFF46 => 0, // Write only
 			0xFF47 => self.palbr,
@@ -237,6 +373,7 @@ impl GPU {
 			0xFF4B => self.winx,
 			0xFF4F..=0xFF6B if self.gbmode != GbMode::Color => 0xFF,
 			0xFF4F => self.vrambank as u8 | 0xFE,
+			0xFF69 | 0xFF6B if !self.vram_accessible() => 0xFF,
 			0xFF68 => 0x40 | self.cbgpal_ind | (if self.cbgpal_inc { 0x80 } else { 0 }),
 			0xFF69 => {
 				let palnum = (self.cbgpal_ind >> 3) as usize;
@@ -259,6 +396,7 @@ impl GPU {
 						| (self.csprit[palnum][colnum][2] << 2)
 				}
 			}
+			0xFF6C => 0xFE | u8::from(self.coordinate_priority),
 			_ => 0xFF,
 		}
 	}
@@ -277,6 +415,8 @@ impl GPU {
 
 	pub fn wb(&mut self, a: u16, v: u8) {
 		match a {
+			0x8000..=0x9FFF if !self.vram_accessible() => {}
+			0xFE00..=0xFE9F if !self.oam_accessible() => {}
 			0x8000..=0x9FFF => self.vram[(self.vrambank * 0x2000) | (a as usize & 0x1FFF)] = v,
 			0xFE00..=0xFE9F => self.voam[a as usize - 0xFE00] = v,
 			0xFF40 => {
@@ -308,6 +448,7 @@ impl GPU {
 					self.line = 0;
 					self.mode = 0;
 					self.wy_trigger = false;
+					self.stat_line = false;
 					self.first_frame = true;
 					self.clear_screen();
 				}
@@ -322,13 +463,14 @@ impl GPU {
 				self.m2_inte = matches!(v & 0x20, 0x20);
 				self.m1_inte = matches!(v & 0x10, 0x10);
 				self.m0_inte = matches!(v & 0x08, 0x08);
+				self.update_stat_line();
 			}
 			0xFF42 => self.scy = v,
 			0xFF43 => self.scx = v,
 			0xFF44 | 0xFF4C | 0xFF4E => {}
 			0xFF45 => {
 				self.lyc = v;
-				self.check_interrupt_lyc();
+				self.update_stat_line();
 			}
 			0xFF46 => panic!("write at 0xFF46 should be handled by mmu"),
 			0xFF47 => {
@@ -354,7 +496,9 @@ impl GPU {
 			0xFF69 => {
 				let palnum = (self.cbgpal_ind >> 3) as usize;
 				let colnum = ((self.cbgpal_ind >> 1) & 0x03) as usize;
-				if matches!(self.cbgpal_ind & 0x01, 0x00) {
+				if !self.vram_accessible() {
+					// The write is lost, but the index still increments.
+				} else if matches!(self.cbgpal_ind & 0x01, 0x00) {
 					self.cbgpal[palnum][colnum][0] = v & 0x1F;
 					self.cbgpal[palnum][colnum][1] =
 						(self.cbgpal[palnum][colnum][1] & 0x18) | (v >> 5);
@@ -374,7 +518,9 @@ impl GPU {
 			0xFF6B => {
 				let palnum = (self.csprit_ind >> 3) as usize;
 				let colnum = ((self.csprit_ind >> 1) & 0x03) as usize;
-				if matches!(self.csprit_ind & 0x01, 0x00) {
+				if !self.vram_accessible() {
+					// The write is lost, but the index still increments.
+				} else if matches!(self.csprit_ind & 0x01, 0x00) {
 					self.csprit[palnum][colnum][0] = v & 0x1F;
 					self.csprit[palnum][colnum][1] =
 						(self.csprit[palnum][colnum][1] & 0x18) | (v >> 5);
@@ -387,28 +533,61 @@ impl GPU {
 					self.csprit_ind = (self.csprit_ind + 1) & 0x3F;
 				}
 			}
+			0xFF6C => self.coordinate_priority = matches!(v & 0x01, 0x01),
 			_ => panic!("gpu does not handle write {a:04X}"),
 		}
 	}
 
+	/// Writes OAM the way OAM DMA does, regardless of what the PPU is doing.
+	pub const fn write_oam(&mut self, index: u16, value: u8) {
+		self.voam[index as usize] = value;
+	}
+
+	/// Loads the colors used for classic games on a Game Boy Color, which
+	/// the shades picked by the monochrome palettes index into.
+	pub fn load_compat_palettes(&mut self, palettes: Palettes) {
+		let [obj0, obj1, ..] = &mut self.csprit;
+		for (pal, colors) in [
+			(&mut self.cbgpal[0], palettes.bg),
+			(obj0, palettes.obj0),
+			(obj1, palettes.obj1),
+		] {
+			for (rgb, color) in pal.iter_mut().zip(colors) {
+				*rgb = [
+					(color & 0x1F) as u8,
+					((color >> 5) & 0x1F) as u8,
+					((color >> 10) & 0x1F) as u8,
+				];
+			}
+		}
+	}
+
 	fn clear_screen(&mut self) {
 		for v in &mut self.data {
 			*v = 255;
 		}
 
+		for v in &mut self.shades {
+			*v = 0;
+		}
+
 		self.updated = true;
 	}
 
 	fn update_pal(&mut self) {
 		for i in 0..4 {
-			self.palb[i] = Self::get_monochrome_pal_val(self.palbr, i);
-			self.pal0[i] = Self::get_monochrome_pal_val(self.pal0r, i);
-			self.pal1[i] = Self::get_monochrome_pal_val(self.pal1r, i);
+			self.palb[i] = Self::get_shade(self.palbr, i);
+			self.pal0[i] = Self::get_shade(self.pal0r, i);
+			self.pal1[i] = Self::get_shade(self.pal1r, i);
 		}
 	}
 
-	const fn get_monochrome_pal_val(value: u8, index: usize) -> u8 {
-		match (value >> (2 * index)) & 0x03 {
+	const fn get_shade(value: u8, index: usize) -> u8 {
+		(value >> (2 * index)) & 0x03
+	}
+
+	const fn get_monochrome_val(shade: u8) -> u8 {
+		match shade {
 			0 => 255,
 			1 => 192,
 			2 => 96,
@@ -422,7 +601,7 @@ impl GPU {
 		}
 
 		for x in 0..SCREEN_W {
-			self.set_color(x, 255);
+			self.set_color(x, 0);
 			self.bgprio[x] = PrioType::Normal;
 		}
 
@@ -430,7 +609,9 @@ impl GPU {
 		self.draw_sprites();
 	}
 
-	fn set_color(&mut self, x: usize, color: u8) {
+	fn set_color(&mut self, x: usize, shade: u8) {
+		let color = Self::get_monochrome_val(shade);
+		self.shades[self.line as usize * SCREEN_W + x] = shade;
 		self.data[self.line as usize * SCREEN_W * 3 + x * 3] = color;
 		self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 1] = color;
 		self.data[self.line as usize * SCREEN_W * 3 + x * 3 + 2] = color;
@@ -544,9 +725,12 @@ impl GPU {
 				let g = self.cbgpal[palnr][colnr][1];
 				let b = self.cbgpal[palnr][colnr][2];
 				self.set_rgb(x, r, g, b);
+			} else if matches!(self.gbmode, GbMode::ColorAsClassic) {
+				let [r, g, b] = self.cbgpal[0][self.palb[colnr] as usize];
+				self.set_rgb(x, r, g, b);
 			} else {
-				let color = self.palb[colnr];
-				self.set_color(x, color);
+				let shade = self.palb[colnr];
+				self.set_color(x, shade);
 			}
 		}
 	}
@@ -577,7 +761,7 @@ impl GPU {
 			}
 		}
 
-		if matches!(self.gbmode, GbMode::Color) {
+		if self.oam_priority() {
 			sprites_to_draw[..sidx].sort_unstable_by(cgb_sprite_order);
 		} else {
 			sprites_to_draw[..sidx].sort_unstable_by(dmg_sprite_order);
@@ -649,16 +833,94 @@ impl GPU {
 						continue 'xloop;
 					}
 
-					let color = if usepal1 {
+					let shade = if usepal1 {
 						self.pal1[colnr]
 					} else {
 						self.pal0[colnr]
 					};
 
-					self.set_color((spritex + x) as usize, color);
+					if matches!(self.gbmode, GbMode::ColorAsClassic) {
+						let [r, g, b] = self.csprit[usize::from(usepal1)][shade as usize];
+						self.set_rgb((spritex + x) as usize, r, g, b);
+					} else {
+						self.set_color((spritex + x) as usize, shade);
+					}
+				}
+			}
+		}
+	}
+
+	/// The row of OAM, made up of two sprites, the PPU is reading during
+	/// mode 2. It moves on to the next row every M-cycle.
+	pub const fn oam_row(&self) -> Option<usize> {
+		if self.lcd_on && matches!(self.mode, 2) {
+			let row = (self.modeclock / 4) as usize;
+			Some(if row < 20 { row } else { 19 })
+		} else {
+			None
+		}
+	}
+
+	/// Corrupts OAM the way the DMG does when the CPU accesses it, or puts
+	/// an address inside it on the bus, while the PPU is reading it.
+	pub fn corrupt_oam(&mut self, corruption: OamCorruption) {
+		let Some(row) = self.oam_row() else {
+			return;
+		};
+
+		// The first row is never affected.
+		if row == 0 {
+			return;
+		}
+
+		let a = self.oam_word(row, 0);
+		let b = self.oam_word(row - 1, 0);
+		let c = self.oam_word(row - 1, 2);
+
+		match corruption {
+			OamCorruption::Write => self.set_oam_word(row, 0, ((a ^ c) & (b ^ c)) ^ c),
+			OamCorruption::Read => self.set_oam_word(row, 0, b | (a & c)),
+			OamCorruption::ReadIncDec => {
+				if (4..19).contains(&row) {
+					let first = self.oam_word(row - 2, 0);
+					let corrupted = (b & (first | a | c)) | (first & a & c);
+					self.set_oam_word(row - 1, 0, corrupted);
+
+					let preceding = (row - 1) * 8;
+					self.voam
+						.copy_within(preceding..preceding + 8, preceding - 8);
+					self.voam
+						.copy_within(preceding..preceding + 8, preceding + 8);
 				}
+
+				self.corrupt_oam(OamCorruption::Read);
+				return;
 			}
 		}
+
+		let preceding = (row - 1) * 8;
+		self.voam
+			.copy_within(preceding + 2..preceding + 8, row * 8 + 2);
+	}
+
+	const fn oam_word(&self, row: usize, word: usize) -> u16 {
+		let i = row * 8 + word * 2;
+		u16::from_le_bytes([self.voam[i], self.voam[i + 1]])
+	}
+
+	fn set_oam_word(&mut self, row: usize, word: usize, value: u16) {
+		let i = row * 8 + word * 2;
+		self.voam[i..i + 2].copy_from_slice(&value.to_le_bytes());
+	}
+
+	/// Whether overlapping sprites are drawn in OAM order, which CGB-family
+	/// hardware does unless OPRI selects the DMG rule, even for classic games.
+	pub const fn oam_priority(&self) -> bool {
+		!matches!(self.gbmode, GbMode::Classic) && !self.coordinate_priority
+	}
+
+	pub const fn lcd_on(&self) -> bool {
+		self.lcd_on
 	}
 
 	pub const fn may_hdma(&self) -> bool {
@@ -666,6 +928,14 @@ impl GPU {
 	}
 }
 
+/// How the CPU touched OAM during an M-cycle that triggers the OAM bug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OamCorruption {
+	Read,
+	Write,
+	ReadIncDec,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum PrioType {
 	Color0,
@@ -686,3 +956,230 @@ fn dmg_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
 fn cgb_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
 	b.2.cmp(&a.2)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{GPU, GbMode, Renderer};
+
+	fn scene(renderer: Renderer) -> GPU {
+		let mut gpu = GPU::classic();
+		gpu.set_renderer(renderer);
+
+		for i in 0..16 {
+			gpu.wb(0x8010 + i, if i % 2 == 0 { 0xF0 } else { 0x3C });
+		}
+
+		for i in 0..0x400 {
+			gpu.wb(0x9800 + i, (i % 3 == 0).into());
+		}
+
+		// A sprite overlapping the left edge, and one behind the background.
+		for (i, value) in [20, 4, 1, 0x00, 40, 50, 1, 0x90].into_iter().enumerate() {
+			gpu.wb(0xFE00 + i as u16, value);
+		}
+
+		gpu.wb(0xFF43, 3);
+		gpu.wb(0xFF47, 0xE4);
+		gpu.wb(0xFF48, 0xD2);
+		gpu.wb(0xFF49, 0x1B);
+		gpu.wb(0xFF40, 0x93);
+
+		gpu
+	}
+
+	#[test]
+	fn fifo_matches_scanline() {
+		let mut scanline = scene(Renderer::Scanline);
+		let mut fifo = scene(Renderer::Fifo);
+
+		for _ in 0..2 * 154 * 114 {
+			scanline.do_cycle(4);
+			fifo.do_cycle(4);
+		}
+
+		assert_eq!(fifo.frames, 2);
+		assert_eq!(fifo.data, scanline.data);
+		assert!((0..4).all(|s| fifo.shades.contains(&s)));
+	}
+
+	#[test]
+	fn fifo_mid_line_change() {
+		let mut gpu = scene(Renderer::Fifo);
+		for _ in 0..154 * 114 {
+			gpu.do_cycle(4);
+		}
+
+		while gpu.modeclock < 80 + 100 {
+			gpu.do_cycle(4);
+		}
+
+		// Switching the background off shows up from the next pixel onwards.
+		gpu.wb(0xFF40, 0x92);
+		while gpu.line == 0 {
+			gpu.do_cycle(4);
+		}
+
+		let row = &gpu.shades[..160];
+		assert!(row[..80].iter().any(|&shade| shade != 0));
+		assert!(row[120..].iter().all(|&shade| shade == 0));
+	}
+
+	#[test]
+	fn access_restrictions() {
+		let mut gpu = GPU::classic();
+		gpu.gbmode = GbMode::Color;
+		gpu.wb(0x8000, 0x12);
+		gpu.wb(0xFE00, 0x34);
+		gpu.wb(0xFF40, 0x91);
+
+		while gpu.mode != 2 {
+			gpu.do_cycle(4);
+		}
+		assert_eq!(gpu.rb(0x8000), 0x12);
+		assert_eq!(gpu.rb(0xFE00), 0xFF);
+		gpu.wb(0xFE00, 0x56);
+
+		while gpu.mode != 3 {
+			gpu.do_cycle(4);
+		}
+		assert_eq!(gpu.rb(0x8000), 0xFF);
+		gpu.wb(0x8000, 0x78);
+		gpu.wb(0xFF68, 0x80);
+		gpu.wb(0xFF69, 0x1F);
+		assert_eq!(gpu.rb(0xFF68) & 0x3F, 1);
+		assert_eq!(gpu.rb(0xFF69), 0xFF);
+
+		gpu.set_restrict_access(false);
+		assert_eq!(gpu.rb(0x8000), 0x12);
+		assert_eq!(gpu.rb(0xFE00), 0x34);
+		assert_eq!(gpu.cbgpal[0][0], [0, 0, 0]);
+
+		// With the LCD off, everything is accessible again.
+		gpu.set_restrict_access(true);
+		gpu.wb(0xFF40, 0x11);
+		assert_eq!(gpu.rb(0x8000), 0x12);
+		assert_eq!(gpu.rb(0xFE00), 0x34);
+	}
+
+	fn overlapping_sprites(renderer: Renderer, opri: u8) -> Vec<u8> {
+		let mut gpu = GPU::classic();
+		gpu.gbmode = GbMode::Color;
+		gpu.set_renderer(renderer);
+		gpu.wb(0xFF6C, opri);
+
+		for i in 0..16 {
+			gpu.wb(0x8010 + i, 0xFF);
+		}
+
+		// Sprite 1 is further left, but sprite 0 comes first in OAM.
+		for (i, value) in [16, 24, 1, 0x00, 16, 20, 1, 0x01].into_iter().enumerate() {
+			gpu.wb(0xFE00 + i as u16, value);
+		}
+
+		gpu.wb(0xFF6A, 0x8E);
+		gpu.wb(0xFF6B, 0x1F);
+		gpu.wb(0xFF6B, 0x00);
+		gpu.wb(0xFF40, 0x82);
+
+		for _ in 0..154 * 114 {
+			gpu.do_cycle(4);
+		}
+
+		gpu.data[..24 * 3].to_vec()
+	}
+
+	#[test]
+	fn object_priority_mode() {
+		for renderer in [Renderer::Scanline, Renderer::Fifo] {
+			let pixel = |data: &[u8], x: usize| data[x * 3..x * 3 + 3].to_vec();
+
+			let data = overlapping_sprites(renderer, 0x00);
+			assert_ne!(pixel(&data, 12), pixel(&data, 20));
+			assert_eq!(pixel(&data, 16), pixel(&data, 20));
+
+			let data = overlapping_sprites(renderer, 0x01);
+			assert_eq!(pixel(&data, 16), pixel(&data, 12));
+		}
+	}
+
+	fn mode3_dots(gpu: &mut GPU) -> u32 {
+		while gpu.line != 1 || gpu.mode != 3 {
+			gpu.do_cycle(1);
+		}
+
+		let mut dots = 0;
+		while gpu.mode == 3 {
+			gpu.do_cycle(1);
+			dots += 1;
+		}
+
+		dots
+	}
+
+	#[test]
+	fn mode3_length() {
+		let mut gpu = GPU::classic();
+		gpu.wb(0xFF40, 0x93);
+		assert_eq!(mode3_dots(&mut gpu), 172);
+
+		let mut gpu = GPU::classic();
+		gpu.wb(0xFF43, 5);
+		gpu.wb(0xFF40, 0x93);
+		assert_eq!(mode3_dots(&mut gpu), 177);
+
+		// The sprite lands on the start of a tile, so waits for the whole fetch.
+		let mut gpu = scene(Renderer::Scanline);
+		gpu.set_restrict_access(false);
+		gpu.wb(0xFF43, 0);
+		for (i, value) in [16, 8, 0, 0].into_iter().enumerate() {
+			gpu.wb(0xFE00 + i as u16, value);
+		}
+		assert_eq!(mode3_dots(&mut gpu), 172 + 6 + 5);
+	}
+
+	#[test]
+	fn stat_interrupt_blocking() {
+		let mut gpu = GPU::classic();
+		gpu.wb(0xFF45, 1);
+		gpu.wb(0xFF41, 0x48);
+		gpu.wb(0xFF40, 0x91);
+
+		let mut requests = 0;
+		while gpu.line < 2 {
+			gpu.do_cycle(4);
+			if gpu.interrupt & 0x02 == 0x02 {
+				requests += 1;
+				gpu.interrupt = 0;
+			}
+		}
+
+		// HBlank on line 0 requests one. The LYC match on line 1 starts while
+		// HBlank is still holding the line, and HBlank on line 1 starts while
+		// the LYC match is.
+		assert_eq!(requests, 1);
+	}
+
+	#[test]
+	fn line_153_reads_as_0() {
+		let mut gpu = GPU::classic();
+		gpu.wb(0xFF45, 0);
+		gpu.wb(0xFF41, 0x40);
+		gpu.wb(0xFF40, 0x91);
+		while gpu.line != 153 {
+			gpu.do_cycle(4);
+		}
+
+		assert_eq!(gpu.rb(0xFF44), 153);
+		gpu.interrupt = 0;
+
+		gpu.do_cycle(4);
+		assert_eq!(gpu.rb(0xFF44), 0);
+		assert_eq!(gpu.rb(0xFF41) & 0x04, 0x04);
+		assert_eq!(gpu.interrupt, 0x02);
+
+		while gpu.line != 0 {
+			gpu.do_cycle(4);
+		}
+		assert_eq!(gpu.interrupt, 0x02);
+	}
+}
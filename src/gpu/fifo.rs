@@ -0,0 +1,406 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::{GPU, SCREEN_W};
+use crate::gb_mode::GbMode;
+
+/// State of the pixel FIFO renderer for the line being drawn.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Fifo {
+	bg: VecDeque<BgPixel>,
+	obj: VecDeque<ObjPixel>,
+	fetcher: FetcherState,
+	fetcher_x: u8,
+	tile: u8,
+	attributes: u8,
+	low: u8,
+	high: u8,
+	window: bool,
+	discard: u8,
+	stall: u8,
+	lcd_x: u8,
+	sprites: Vec<Sprite>,
+	next_sprite: usize,
+	sprite_stall: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum FetcherState {
+	#[default]
+	Tile,
+	TileTicked,
+	DataLow,
+	DataLowTicked,
+	DataHigh,
+	DataHighTicked,
+	Push,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BgPixel {
+	color: u8,
+	palette: u8,
+	priority: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct ObjPixel {
+	color: u8,
+	palette: u8,
+	behind_bg: bool,
+	index: u8,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Sprite {
+	x: u8,
+	y: u8,
+	tile: u8,
+	flags: u8,
+	index: u8,
+}
+
+impl GPU {
+	/// Advances the PPU by a single dot, drawing at most one pixel.
+	pub(super) fn fifo_dot(&mut self) {
+		self.modeclock += 1;
+		if self.modeclock >= 456 {
+			self.modeclock = 0;
+			self.line = (self.line + 1) % 154;
+			self.update_stat_line();
+
+			if self.line >= 144 {
+				if !matches!(self.mode, 1) {
+					self.change_mode(1);
+				}
+			} else {
+				self.change_mode(2);
+			}
+
+			return;
+		}
+
+		if self.line >= 144 {
+			if self.line == 153 && self.modeclock == 4 {
+				self.update_stat_line();
+			}
+
+			return;
+		}
+
+		match self.mode {
+			2 if self.modeclock >= 80 => {
+				self.change_mode(3);
+				self.start_fifo_line();
+			}
+			3 => {
+				self.fifo_step();
+				if usize::from(self.fifo.lcd_x) >= SCREEN_W {
+					self.change_mode(0);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	fn start_fifo_line(&mut self) {
+		let line = i32::from(self.line);
+		let sprite_size = self.sprite_size as i32;
+
+		let mut sprites = Vec::with_capacity(10);
+		for index in 0..40u8 {
+			let base = usize::from(index) * 4;
+			let y = self.voam[base];
+			let top = i32::from(y) - 16;
+			if line < top || line >= top + sprite_size {
+				continue;
+			}
+
+			sprites.push(Sprite {
+				x: self.voam[base + 1],
+				y,
+				tile: self.voam[base + 2],
+				flags: self.voam[base + 3],
+				index,
+			});
+			if sprites.len() >= 10 {
+				break;
+			}
+		}
+
+		// Sprites are fetched from left to right, the stable sort keeping ties
+		// in OAM order.
+		sprites.sort_by_key(|sprite| sprite.x);
+
+		self.fifo = Fifo {
+			discard: self.scx & 0x07,
+			// The first tile fetched on every line is thrown away.
+			stall: 6,
+			sprites,
+			..Fifo::default()
+		};
+	}
+
+	fn fifo_step(&mut self) {
+		if self.fifo.stall > 0 {
+			self.fifo.stall -= 1;
+			return;
+		}
+
+		if self.fifo.sprite_stall > 0 {
+			self.fifo.sprite_stall -= 1;
+			if self.fifo.sprite_stall == 0 {
+				self.fetch_sprite();
+			}
+			return;
+		}
+
+		if let Some(sprite) = self.fifo.sprites.get(self.fifo.next_sprite)
+			&& sprite.x <= self.fifo.lcd_x + 8
+		{
+			if self.sprite_on && sprite.x > 0 {
+				self.fifo.sprite_stall = 6;
+			} else {
+				self.fifo.next_sprite += 1;
+			}
+			return;
+		}
+
+		self.step_fetcher();
+		self.push_pixel();
+	}
+
+	fn step_fetcher(&mut self) {
+		use FetcherState::{
+			DataHigh, DataHighTicked, DataLow, DataLowTicked, Push, Tile, TileTicked,
+		};
+
+		self.fifo.fetcher = match self.fifo.fetcher {
+			Tile => {
+				let address = self.fetcher_map_address();
+				self.fifo.tile = self.rbvram0(address);
+				self.fifo.attributes = if matches!(self.gbmode, GbMode::Color) {
+					self.rbvram1(address)
+				} else {
+					0
+				};
+				TileTicked
+			}
+			TileTicked => DataLow,
+			DataLow => {
+				self.fifo.low = self.read_tile_data(0);
+				DataLowTicked
+			}
+			DataLowTicked => DataHigh,
+			DataHigh => {
+				self.fifo.high = self.read_tile_data(1);
+				DataHighTicked
+			}
+			Push if self.fifo.bg.is_empty() => {
+				let xflip = matches!(self.fifo.attributes & 0x20, 0x20);
+				for i in 0..8 {
+					let bit = if xflip { i } else { 7 - i };
+					self.fifo.bg.push_back(BgPixel {
+						color: ((self.fifo.low >> bit) & 1) | (((self.fifo.high >> bit) & 1) << 1),
+						palette: self.fifo.attributes & 0x07,
+						priority: matches!(self.fifo.attributes & 0x80, 0x80),
+					});
+				}
+
+				self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+				Tile
+			}
+			DataHighTicked | Push => Push,
+		};
+	}
+
+	fn fetcher_map_address(&self) -> u16 {
+		if self.fifo.window {
+			let y = self.wy_pos as u16;
+			self.win_tilemap + ((y >> 3) & 31) * 32 + (u16::from(self.fifo.fetcher_x) & 31)
+		} else {
+			let y = u16::from(self.scy.wrapping_add(self.line));
+			let x = (u16::from(self.scx >> 3) + u16::from(self.fifo.fetcher_x)) & 31;
+			self.bg_tilemap + ((y >> 3) & 31) * 32 + x
+		}
+	}
+
+	fn read_tile_data(&self, byte: u16) -> u8 {
+		let y = if self.fifo.window {
+			self.wy_pos as u16
+		} else {
+			u16::from(self.scy.wrapping_add(self.line))
+		};
+
+		let row = if matches!(self.fifo.attributes & 0x40, 0x40) {
+			7 - (y & 0x07)
+		} else {
+			y & 0x07
+		};
+
+		let tile = self.fifo.tile;
+		let address = self.tilebase
+			+ (if matches!(self.tilebase, 0x8000) {
+				u16::from(tile)
+			} else {
+				(i16::from(tile as i8) + 128) as u16
+			}) * 16 + row * 2
+			+ byte;
+
+		if matches!(self.fifo.attributes & 0x08, 0x08) {
+			self.rbvram1(address)
+		} else {
+			self.rbvram0(address)
+		}
+	}
+
+	fn fetch_sprite(&mut self) {
+		let sprite = self.fifo.sprites[self.fifo.next_sprite];
+		self.fifo.next_sprite += 1;
+
+		let sprite_size = self.sprite_size as u16;
+		let mut row = u16::from(self.line.wrapping_sub(sprite.y.wrapping_sub(16)));
+		if matches!(sprite.flags & 0x40, 0x40) {
+			row = sprite_size - 1 - row;
+		}
+
+		let tile = if matches!(sprite_size, 16) {
+			sprite.tile & 0xFE
+		} else {
+			sprite.tile
+		};
+		let address = 0x8000 + u16::from(tile) * 16 + row * 2;
+		let color_mode = matches!(self.gbmode, GbMode::Color);
+		let index_priority = self.oam_priority();
+		let (low, high) = if color_mode && matches!(sprite.flags & 0x08, 0x08) {
+			(self.rbvram1(address), self.rbvram1(address + 1))
+		} else {
+			(self.rbvram0(address), self.rbvram0(address + 1))
+		};
+
+		// Sprites partly off the left edge lose their first pixels.
+		let skip = 8u8.saturating_sub(sprite.x);
+		while self.fifo.obj.len() < 8 {
+			self.fifo.obj.push_back(ObjPixel::default());
+		}
+
+		let xflip = matches!(sprite.flags & 0x20, 0x20);
+		for i in skip..8 {
+			let bit = if xflip { i } else { 7 - i };
+			let pixel = ObjPixel {
+				color: ((low >> bit) & 1) | (((high >> bit) & 1) << 1),
+				palette: if color_mode {
+					sprite.flags & 0x07
+				} else {
+					(sprite.flags >> 4) & 0x01
+				},
+				behind_bg: matches!(sprite.flags & 0x80, 0x80),
+				index: sprite.index,
+			};
+
+			// Earlier sprites win, except that in color mode the OAM index
+			// decides instead, unless OPRI asks for coordinate priority.
+			let slot = &mut self.fifo.obj[usize::from(i - skip)];
+			if slot.color == 0 || (index_priority && pixel.color != 0 && pixel.index < slot.index) {
+				*slot = pixel;
+			}
+		}
+	}
+
+	fn push_pixel(&mut self) {
+		if !self.fifo.window
+			&& self.win_on
+			&& self.wy_trigger
+			&& self.winx <= 166
+			&& u16::from(self.fifo.lcd_x) + 7 >= u16::from(self.winx)
+		{
+			self.fifo.window = true;
+			self.fifo.bg.clear();
+			self.fifo.fetcher = FetcherState::Tile;
+			self.fifo.fetcher_x = 0;
+			self.fifo.discard = 7u8.saturating_sub(self.winx);
+			self.wy_pos += 1;
+			return;
+		}
+
+		let Some(bg) = self.fifo.bg.pop_front() else {
+			return;
+		};
+
+		if self.fifo.discard > 0 {
+			self.fifo.discard -= 1;
+			return;
+		}
+
+		let obj = self.fifo.obj.pop_front().unwrap_or_default();
+		let x = usize::from(self.fifo.lcd_x);
+		self.fifo.lcd_x += 1;
+
+		if self.first_frame {
+			return;
+		}
+
+		let color_mode = matches!(self.gbmode, GbMode::Color);
+		let bg_color = if color_mode || self.lcdc0 {
+			bg.color
+		} else {
+			0
+		};
+
+		// On the CGB, LCDC bit 0 turns off every background priority flag.
+		let bg_wins = bg_color != 0
+			&& if color_mode {
+				self.lcdc0 && (bg.priority || obj.behind_bg)
+			} else {
+				obj.behind_bg
+			};
+		let obj_visible = self.sprite_on && obj.color != 0 && !bg_wins;
+
+		match (obj_visible, self.gbmode) {
+			(true, GbMode::Color) => {
+				let [r, g, b] = self.csprit[usize::from(obj.palette)][usize::from(obj.color)];
+				self.set_rgb(x, r, g, b);
+			}
+			(true, GbMode::ColorAsClassic) => {
+				let pal = if obj.palette == 0 {
+					self.pal0
+				} else {
+					self.pal1
+				};
+				let shade = pal[usize::from(obj.color)];
+				let [r, g, b] = self.csprit[usize::from(obj.palette)][usize::from(shade)];
+				self.set_rgb(x, r, g, b);
+			}
+			(true, GbMode::Classic) => {
+				let pal = if obj.palette == 0 {
+					self.pal0
+				} else {
+					self.pal1
+				};
+				self.set_color(x, pal[usize::from(obj.color)]);
+			}
+			(false, GbMode::Color) => {
+				let [r, g, b] = self.cbgpal[usize::from(bg.palette)][usize::from(bg_color)];
+				self.set_rgb(x, r, g, b);
+			}
+			(false, GbMode::ColorAsClassic) => {
+				let shade = if self.lcdc0 {
+					self.palb[usize::from(bg_color)]
+				} else {
+					0
+				};
+				let [r, g, b] = self.cbgpal[0][usize::from(shade)];
+				self.set_rgb(x, r, g, b);
+			}
+			(false, GbMode::Classic) => {
+				let shade = if self.lcdc0 {
+					self.palb[usize::from(bg_color)]
+				} else {
+					0
+				};
+				self.set_color(x, shade);
+			}
+		}
+	}
+}
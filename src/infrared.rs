@@ -0,0 +1,248 @@
+use std::sync::{
+	Arc,
+	atomic::{AtomicBool, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{device::Device, lockstep::Lockstep};
+
+/// The CGB infrared port, RP at 0xFF56.
+#[derive(Serialize, Deserialize)]
+pub struct Infrared {
+	control: u8,
+	#[serde(skip)]
+	callback: Option<Box<dyn InfraredCallback>>,
+}
+
+impl Infrared {
+	pub const fn new() -> Self {
+		Self {
+			control: 0,
+			callback: None,
+		}
+	}
+
+	pub fn wb(&mut self, a: u16, v: u8) {
+		match a {
+			0xFF56 => {
+				let led_changed = (self.control ^ v) & 0x01 == 0x01;
+				self.control = v & 0xC1;
+				if led_changed && let Some(callback) = &mut self.callback {
+					callback.set_led(matches!(v & 0x01, 0x01));
+				}
+			}
+			_ => panic!("infrared does not handle write address {a:4X}"),
+		}
+	}
+
+	/// Bit 1 reads 0 while light is received, but only with reading enabled
+	/// through bits 6 and 7.
+	pub fn rb(&mut self, a: u16) -> u8 {
+		match a {
+			0xFF56 => {
+				let receiving = matches!(self.control & 0xC0, 0xC0)
+					&& self
+						.callback
+						.as_mut()
+						.is_some_and(|callback| callback.receiving());
+
+				self.control | 0x3C | if receiving { 0x00 } else { 0x02 }
+			}
+			_ => panic!("infrared does not handle read address {a:4X}"),
+		}
+	}
+
+	const fn led_on(&self) -> bool {
+		matches!(self.control & 0x01, 0x01)
+	}
+
+	pub fn set_callback(&mut self, mut cb: Box<dyn InfraredCallback>) {
+		cb.set_led(self.led_on());
+		self.callback = Some(cb);
+	}
+
+	pub fn clear_callback(&mut self) {
+		self.callback = None;
+	}
+}
+
+/// Something on the other side of the infrared port.
+pub trait InfraredCallback: Send {
+	/// Called whenever the game turns the LED on or off.
+	fn set_led(&mut self, on: bool);
+
+	/// Whether the sensor currently sees light.
+	fn receiving(&mut self) -> bool;
+}
+
+/// Two devices pointed at each other's infrared ports. They are run in
+/// lockstep, so each sees the other's LED change within an instruction.
+pub struct InfraredLink {
+	devices: Lockstep,
+}
+
+impl InfraredLink {
+	/// Connects both devices, replacing whatever was connected to their
+	/// infrared ports.
+	#[must_use]
+	pub fn new(mut first: Device, mut second: Device) -> Self {
+		let (first_port, second_port) = InfraredPort::pair();
+		first.set_infrared_callback(Box::new(first_port));
+		second.set_infrared_callback(Box::new(second_port));
+
+		Self {
+			devices: Lockstep::new(first, second),
+		}
+	}
+
+	/// Runs both devices for at least `cycles` clock cycles.
+	pub fn run_cycles(&mut self, cycles: u32) {
+		self.devices.run_cycles(cycles);
+	}
+
+	/// Runs both devices until the first one reaches its next vertical blank,
+	/// or for a frame's worth of cycles while it isn't drawing.
+	pub fn run_frame(&mut self) {
+		self.devices.run_frame();
+	}
+
+	#[must_use]
+	pub const fn first(&self) -> &Device {
+		&self.devices.first
+	}
+
+	pub const fn first_mut(&mut self) -> &mut Device {
+		&mut self.devices.first
+	}
+
+	#[must_use]
+	pub const fn second(&self) -> &Device {
+		&self.devices.second
+	}
+
+	pub const fn second_mut(&mut self) -> &mut Device {
+		&mut self.devices.second
+	}
+
+	/// Disconnects both devices and hands them back.
+	#[must_use]
+	pub fn into_devices(self) -> (Device, Device) {
+		let Lockstep {
+			mut first,
+			mut second,
+			..
+		} = self.devices;
+		first.clear_infrared_callback();
+		second.clear_infrared_callback();
+		(first, second)
+	}
+}
+
+/// One end of an infrared connection. Each end sees the other's LED.
+struct InfraredPort {
+	led: Arc<AtomicBool>,
+	remote: Arc<AtomicBool>,
+}
+
+impl InfraredPort {
+	fn pair() -> (Self, Self) {
+		let first = Arc::new(AtomicBool::new(false));
+		let second = Arc::new(AtomicBool::new(false));
+
+		(
+			Self {
+				led: Arc::clone(&first),
+				remote: Arc::clone(&second),
+			},
+			Self {
+				led: second,
+				remote: first,
+			},
+		)
+	}
+}
+
+impl InfraredCallback for InfraredPort {
+	fn set_led(&mut self, on: bool) {
+		self.led.store(on, Ordering::Relaxed);
+	}
+
+	fn receiving(&mut self) -> bool {
+		self.remote.load(Ordering::Relaxed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Infrared, InfraredLink, InfraredPort};
+	use crate::{Device, DeviceBuilder};
+
+	/// A color cartridge with RAM running `program` from 0x150.
+	fn cgb_with_program(program: &[u8]) -> Device {
+		let mut rom = vec![0; 0x8000];
+		rom[0x143] = 0x80;
+		rom[0x147] = 0x03;
+		rom[0x149] = 0x02;
+		rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+		rom[0x150..0x150 + program.len()].copy_from_slice(program);
+
+		DeviceBuilder::new().skip_checksum(true).build(rom).unwrap()
+	}
+
+	#[test]
+	fn linked_ports() {
+		let (first, second) = InfraredPort::pair();
+		let mut a = Infrared::new();
+		let mut b = Infrared::new();
+		a.set_callback(Box::new(first));
+		b.set_callback(Box::new(second));
+
+		b.wb(0xFF56, 0xC0);
+		assert_eq!(b.rb(0xFF56), 0xFE);
+
+		a.wb(0xFF56, 0x01);
+		assert_eq!(b.rb(0xFF56), 0xFC);
+
+		// Nothing is seen with reading disabled.
+		b.wb(0xFF56, 0x00);
+		assert_eq!(b.rb(0xFF56), 0x3E);
+
+		a.wb(0xFF56, 0x00);
+		b.wb(0xFF56, 0xC0);
+		assert_eq!(b.rb(0xFF56), 0xFE);
+	}
+
+	#[test]
+	fn linked_devices() {
+		// Flashes the LED for a few dozen cycles after a delay, which the other
+		// side only catches when both run in step.
+		#[rustfmt::skip]
+		let sender = cgb_with_program(&[
+			0x06, 0x00, 0x05, 0x20, 0xFD, // LD B, 0; wait: DEC B; JR NZ, wait
+			0x3E, 0x01, 0xE0, 0x56,       // LD A, 1; LDH (0x56), A
+			0x00, 0x00, 0x00, 0x00,       // NOP x8
+			0x00, 0x00, 0x00, 0x00,
+			0xAF, 0xE0, 0x56,             // XOR A; LDH (0x56), A
+			0x18, 0xFE,                   // JR -2
+		]);
+
+		// Waits for light, then stores 1 at 0xA000.
+		#[rustfmt::skip]
+		let receiver = cgb_with_program(&[
+			0x3E, 0x0A, 0xEA, 0x00, 0x00, // LD A, 0x0A; LD (0x0000), A
+			0x3E, 0xC0, 0xE0, 0x56,       // LD A, 0xC0; LDH (0x56), A
+			0xF0, 0x56, 0xCB, 0x4F,       // wait: LDH A, (0x56); BIT 1, A
+			0x20, 0xFA,                   // JR NZ, wait
+			0x3E, 0x01, 0xEA, 0x00, 0xA0, // LD A, 1; LD (0xA000), A
+			0x18, 0xFE,                   // JR -2
+		]);
+
+		let mut link = InfraredLink::new(sender, receiver);
+		for _ in 0..2 {
+			link.run_frame();
+		}
+
+		assert_eq!(link.second().dump_ram()[0], 1);
+	}
+}
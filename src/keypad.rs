@@ -27,6 +27,12 @@ impl Keypad {
 		self.update();
 	}
 
+	/// Whether any button in the currently selected rows is held down, which
+	/// is what wakes the CPU up from `STOP`.
+	pub const fn any_pressed(&self) -> bool {
+		!matches!(self.data & 0x0F, 0x0F)
+	}
+
 	const fn update(&mut self) {
 		let old_values = self.data & 0xF;
 		let mut new_values = 0xF;
@@ -1,23 +1,38 @@
 #![cfg_attr(docsrs, feature(doc_auto_cfg, doc_cfg))]
 #![allow(clippy::upper_case_acronyms)]
 
+mod bgb;
+mod bus;
+mod compat_palettes;
 mod cpu;
 mod device;
 mod gb_mode;
 mod gpu;
+mod infrared;
 mod keypad;
+mod link;
+mod lockstep;
 mod mbc;
 mod mmu;
 mod printer;
 mod registers;
 mod serial;
+mod sgb;
 mod sound;
 mod timer;
 
 pub use self::{
-	gpu::{SCREEN_H, SCREEN_W},
+	bgb::BgbLink,
+	compat_palettes::CompatPalette,
+	cpu::Lockup,
+	device::{CLOCK_SPEED, CYCLES_PER_FRAME, Device, DeviceBuilder},
+	gb_mode::Model,
+	gpu::{Renderer, SCREEN_H, SCREEN_W},
+	infrared::{InfraredCallback, InfraredLink},
 	keypad::KeypadKey,
+	link::LinkCable,
 	serial::SerialCallback,
+	sgb::{SGB_SCREEN_H, SGB_SCREEN_W},
 	sound::AudioPlayer,
 };
 
@@ -0,0 +1,235 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::{device::Device, lockstep::Lockstep, serial::SerialCallback};
+
+/// A link cable between two devices in the same process. They are run in
+/// lockstep, so neither gets more than an instruction ahead of the other.
+///
+/// Whichever side starts a transfer on its own clock drives it. The other
+/// side receives the byte only if it was already waiting on an external
+/// clock, just as on hardware.
+pub struct LinkCable {
+	devices: Lockstep,
+}
+
+impl LinkCable {
+	/// Plugs the cable into both devices, replacing whatever was connected to
+	/// their serial ports.
+	#[must_use]
+	pub fn new(mut first: Device, mut second: Device) -> Self {
+		let wire = Arc::new(Mutex::new(Wire::default()));
+		first.set_serial_callback(Box::new(LinkPort {
+			side: 0,
+			wire: Arc::clone(&wire),
+		}));
+		second.set_serial_callback(Box::new(LinkPort { side: 1, wire }));
+
+		Self {
+			devices: Lockstep::new(first, second),
+		}
+	}
+
+	/// Runs both devices for at least `cycles` clock cycles.
+	pub fn run_cycles(&mut self, cycles: u32) {
+		self.devices.run_cycles(cycles);
+	}
+
+	/// Runs both devices until the first one reaches its next vertical blank,
+	/// or for a frame's worth of cycles while it isn't drawing.
+	pub fn run_frame(&mut self) {
+		self.devices.run_frame();
+	}
+
+	#[must_use]
+	pub const fn first(&self) -> &Device {
+		&self.devices.first
+	}
+
+	pub const fn first_mut(&mut self) -> &mut Device {
+		&mut self.devices.first
+	}
+
+	#[must_use]
+	pub const fn second(&self) -> &Device {
+		&self.devices.second
+	}
+
+	pub const fn second_mut(&mut self) -> &mut Device {
+		&mut self.devices.second
+	}
+
+	/// Unplugs the cable and hands both devices back.
+	#[must_use]
+	pub fn into_devices(self) -> (Device, Device) {
+		let Lockstep {
+			mut first,
+			mut second,
+			..
+		} = self.devices;
+		first.clear_serial_callback();
+		second.clear_serial_callback();
+		(first, second)
+	}
+}
+
+/// What is on the wire between the two ends.
+#[derive(Default)]
+struct Wire {
+	/// The byte each side has ready while waiting on an external clock.
+	waiting: [Option<u8>; 2],
+	/// Bytes the other side started clocking in, with its bit period, not
+	/// yet picked up.
+	delivered: [Option<(u8, u32)>; 2],
+	/// The bit period each side uses on its own clock.
+	period: [u32; 2],
+}
+
+struct LinkPort {
+	side: usize,
+	wire: Arc<Mutex<Wire>>,
+}
+
+impl SerialCallback for LinkPort {
+	fn write_control(&mut self, control: u8) {
+		let mut wire = self.wire.lock().unwrap_or_else(PoisonError::into_inner);
+		if !matches!(control & 0x81, 0x80) {
+			wire.waiting[self.side] = None;
+			wire.delivered[self.side] = None;
+		}
+
+		wire.period[self.side] = if matches!(control & 0x02, 0x02) {
+			16
+		} else {
+			512
+		};
+	}
+
+	fn call(&mut self, value: u8) -> Option<u8> {
+		let mut wire = self.wire.lock().unwrap_or_else(PoisonError::into_inner);
+		wire.waiting[self.side] = None;
+		wire.delivered[self.side] = None;
+
+		let other = 1 - self.side;
+		let received = wire.waiting[other].take()?;
+		wire.delivered[other] = Some((value, wire.period[self.side]));
+		Some(received)
+	}
+
+	fn external_clock(&mut self, value: u8) -> Option<(u8, u32)> {
+		let mut wire = self.wire.lock().unwrap_or_else(PoisonError::into_inner);
+		let received = wire.delivered[self.side].take();
+		wire.waiting[self.side] = received.is_none().then_some(value);
+		received
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+
+	use super::{LinkCable, LinkPort, Wire};
+	use crate::{Device, DeviceBuilder, serial::SerialCallback};
+
+	/// A cartridge with RAM that sends `byte` using `control`, then stores
+	/// the byte it got back at 0xA000.
+	fn transfer(byte: u8, control: u8) -> Device {
+		let mut rom = vec![0; 0x8000];
+		rom[0x147] = 0x03;
+		rom[0x149] = 0x02;
+
+		#[rustfmt::skip]
+		let program = [
+			0x3E, 0x0A, 0xEA, 0x00, 0x00, // LD A, 0x0A; LD (0x0000), A
+			0x06, 0x00, 0x05, 0x20, 0xFD, // LD B, 0; wait: DEC B; JR NZ, wait
+			0x3E, byte, 0xE0, 0x01,       // LD A, byte; LDH (0x01), A
+			0x3E, control, 0xE0, 0x02,    // LD A, control; LDH (0x02), A
+			0xF0, 0x02, 0xCB, 0x7F,       // busy: LDH A, (0x02); BIT 7, A
+			0x20, 0xFA,                   // JR NZ, busy
+			0xF0, 0x01, 0xEA, 0x00, 0xA0, // LDH A, (0x01); LD (0xA000), A
+			0x18, 0xFE,                   // JR -2
+		];
+		rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+
+		DeviceBuilder::new().skip_checksum(true).build(rom).unwrap()
+	}
+
+	#[test]
+	fn exchanges_bytes() {
+		// Both wait just as long before sending, so the slave gets a head
+		// start to be ready when the master sends.
+		let mut slave = transfer(0x99, 0x80);
+		slave.run_cycles(100);
+
+		let mut cable = LinkCable::new(transfer(0x42, 0x81), slave);
+		for _ in 0..4 {
+			cable.run_frame();
+		}
+
+		assert_eq!(cable.first().dump_ram()[0], 0x99);
+		assert_eq!(cable.second().dump_ram()[0], 0x42);
+	}
+
+	#[test]
+	fn switching_to_internal_clock() {
+		let wire = Arc::new(Mutex::new(Wire::default()));
+		let mut a = LinkPort {
+			side: 0,
+			wire: Arc::clone(&wire),
+		};
+		let mut b = LinkPort { side: 1, wire };
+
+		// A listens, then gives up and sends on its own clock instead.
+		a.write_control(0x80);
+		assert_eq!(a.external_clock(0x11), None);
+		a.write_control(0x81);
+		assert_eq!(a.call(0x22), None);
+
+		// B doesn't get the byte A stopped offering, and A doesn't get one
+		// sent while it wasn't listening.
+		b.write_control(0x81);
+		assert_eq!(b.call(0x33), None);
+		a.write_control(0x80);
+		assert_eq!(a.external_clock(0x44), None);
+
+		b.write_control(0x83);
+		assert_eq!(b.call(0x55), Some(0x44));
+		assert_eq!(a.external_clock(0x44), Some((0x55, 16)));
+	}
+
+	#[test]
+	fn slave_shifts_at_master_rate() {
+		let mut slave = transfer(0x99, 0x80);
+		slave.run_cycles(100);
+		let mut cable = LinkCable::new(transfer(0x42, 0x81), slave);
+
+		// Both finish together, 8 bits of 512 cycles after the master starts,
+		// which is itself after a delay loop about as long.
+		let mut finished = [None; 2];
+		while finished.contains(&None) && cable.devices.elapsed(0) < 100_000 {
+			cable.run_cycles(4);
+			for (side, ram) in [cable.first().dump_ram()[0], cable.second().dump_ram()[0]]
+				.into_iter()
+				.enumerate()
+			{
+				if finished[side].is_none() && matches!(ram, 0x42 | 0x99) {
+					finished[side] = Some(cable.devices.elapsed(side));
+				}
+			}
+		}
+
+		let [Some(master), Some(slave)] = finished else {
+			panic!("transfer didn't finish: {finished:?}");
+		};
+		assert!(master.abs_diff(slave) < 64, "{master} {slave}");
+		assert!(master > 512 * 16);
+	}
+
+	#[test]
+	fn nobody_listening() {
+		let mut cable = LinkCable::new(transfer(0x42, 0x81), transfer(0x99, 0x00));
+		cable.run_cycles(100_000);
+
+		assert_eq!(cable.first().dump_ram()[0], 0xFF);
+		assert_eq!(cable.second().dump_ram()[0], 0x99);
+	}
+}
@@ -0,0 +1,52 @@
+use super::device::{CYCLES_PER_FRAME, Device};
+
+/// Two devices run in lockstep, for connections that need each side to see
+/// what the other does as it happens.
+pub(crate) struct Lockstep {
+	pub(crate) first: Device,
+	pub(crate) second: Device,
+	start: [u64; 2],
+}
+
+impl Lockstep {
+	pub(crate) const fn new(first: Device, second: Device) -> Self {
+		let start = [first.cycles(), second.cycles()];
+		Self {
+			first,
+			second,
+			start,
+		}
+	}
+
+	pub(crate) fn run_cycles(&mut self, cycles: u32) {
+		let target = self.elapsed(0).max(self.elapsed(1)) + u64::from(cycles);
+		while self.elapsed(0) < target || self.elapsed(1) < target {
+			self.step();
+		}
+	}
+
+	pub(crate) fn run_frame(&mut self) {
+		let frame = self.first.frames();
+		let start = self.elapsed(0);
+		while self.first.frames() == frame {
+			self.step();
+			if !self.first.drawing() && self.elapsed(0) - start >= u64::from(CYCLES_PER_FRAME) {
+				break;
+			}
+		}
+	}
+
+	/// Runs an instruction on whichever device is behind.
+	fn step(&mut self) {
+		if self.elapsed(0) <= self.elapsed(1) {
+			self.first.do_tick();
+		} else {
+			self.second.do_tick();
+		}
+	}
+
+	pub(crate) const fn elapsed(&self, side: usize) -> u64 {
+		let device = if side == 0 { &self.first } else { &self.second };
+		device.cycles() - self.start[side]
+	}
+}
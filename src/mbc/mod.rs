@@ -171,8 +171,8 @@ const fn rom_banks(v: u8) -> usize {
 
 fn check_checksum(data: &[u8]) -> StrResult<()> {
 	let mut value = 0u8;
-	for i in 0x134..0x14D {
-		value = value.wrapping_sub(data[i]).wrapping_sub(1);
+	for &b in &data[0x134..0x14D] {
+		value = value.wrapping_sub(b).wrapping_sub(1);
 	}
 
 	if data[0x14D] == value {
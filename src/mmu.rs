@@ -4,12 +4,16 @@ use serde::{Deserialize, Serialize};
 
 use super::{
 	StrResult,
-	gb_mode::{GbMode, GbSpeed},
-	gpu::GPU,
+	bus::Bus,
+	compat_palettes::Palettes,
+	gb_mode::{GbMode, GbSpeed, Model},
+	gpu::{GPU, OamCorruption},
+	infrared::Infrared,
 	keypad::Keypad,
 	mbc,
 	serial::{Serial, SerialCallback},
-	sound::Sound,
+	sgb::Sgb,
+	sound::{AudioPlayer, Sound},
 	timer::Timer,
 };
 
@@ -26,18 +30,28 @@ pub struct MMU {
 	pub inte: u8,
 	pub intf: u8,
 	pub serial: Serial,
+	pub infrared: Infrared,
 	pub timer: Timer,
 	pub keypad: Keypad,
+	pub sgb: Option<Box<Sgb>>,
 	pub gpu: GPU,
 	#[serde(skip)]
 	pub sound: Option<Sound>,
+	oamdma_reg: u8,
+	oamdma_delay: u8,
+	oamdma_src: u16,
+	oamdma_pos: Option<u16>,
+	oamdma_byte: u8,
+	oam_bug: Option<OamCorruption>,
 	hdma_status: DMAType,
 	hdma_src: u16,
 	hdma_dst: u16,
 	hdma_len: u8,
 	wram_bank: usize,
 	pub mbc: Box<dyn mbc::MBC + 'static>,
+	boot_rom: Vec<u8>,
 	pub gbmode: GbMode,
+	pub model: Model,
 	gbspeed: GbSpeed,
 	speed_switch_req: bool,
 	undocumented_cgb_regs: [u8; 3], // 0xFF72, 0xFF73, 0xFF75
@@ -46,8 +60,11 @@ pub struct MMU {
 impl MMU {
 	pub fn classic(
 		cart: Box<dyn mbc::MBC + 'static>,
+		model: Model,
+		boot_rom: Option<Vec<u8>>,
 		serial_callback: Option<Box<dyn SerialCallback>>,
 	) -> StrResult<Self> {
+		let boot_rom = check_boot_rom(model, boot_rom)?;
 		let serial = match serial_callback {
 			Some(cb) => Serial::with_callback(cb),
 			None => Serial::new(),
@@ -61,14 +78,24 @@ impl MMU {
 			inte: 0,
 			intf: 0,
 			serial,
+			infrared: Infrared::new(),
 			timer: Timer::new(),
 			keypad: Keypad::new(),
+			sgb: None,
 			gpu: GPU::classic(),
 			sound: None,
 			mbc: cart,
+			boot_rom,
 			gbmode: GbMode::Classic,
+			model,
 			gbspeed: GbSpeed::Single,
 			speed_switch_req: false,
+			oamdma_reg: 0xFF,
+			oamdma_delay: 0,
+			oamdma_src: 0,
+			oamdma_pos: None,
+			oamdma_byte: 0xFF,
+			oam_bug: None,
 			hdma_src: 0,
 			hdma_dst: 0,
 			hdma_status: DMAType::NoDMA,
@@ -78,18 +105,29 @@ impl MMU {
 
 		fill_random(&mut res.wram, 42);
 
-		if matches!(res.rb(0x0143), 0xC0) {
+		if matches!(res.mbc.read_rom(0x0143), 0xC0) {
 			return Err("this game does not work in classic mode");
 		}
 
-		res.set_initial();
+		// The SGB only listens for packets from games that declare support.
+		if model.is_sgb() && res.mbc.read_rom(0x0146) == 0x03 && res.mbc.read_rom(0x014B) == 0x33 {
+			res.sgb = Some(Box::new(Sgb::new()));
+		}
+
+		if !res.boot_rom_mapped() {
+			res.set_initial();
+		}
+
 		Ok(res)
 	}
 
 	pub fn cgb(
 		cart: Box<dyn mbc::MBC + 'static>,
+		model: Model,
+		boot_rom: Option<Vec<u8>>,
 		serial_callback: Option<Box<dyn SerialCallback>>,
 	) -> StrResult<Self> {
+		let boot_rom = check_boot_rom(model, boot_rom)?;
 		let serial = match serial_callback {
 			Some(cb) => Serial::with_callback(cb),
 			None => Serial::new(),
@@ -103,14 +141,24 @@ impl MMU {
 			inte: 0,
 			intf: 0,
 			serial,
+			infrared: Infrared::new(),
 			timer: Timer::new(),
 			keypad: Keypad::new(),
+			sgb: None,
 			gpu: GPU::cgb(),
 			sound: None,
 			mbc: cart,
+			boot_rom,
 			gbmode: GbMode::Color,
+			model,
 			gbspeed: GbSpeed::Single,
 			speed_switch_req: false,
+			oamdma_reg: 0xFF,
+			oamdma_delay: 0,
+			oamdma_src: 0,
+			oamdma_pos: None,
+			oamdma_byte: 0xFF,
+			oam_bug: None,
 			hdma_src: 0,
 			hdma_dst: 0,
 			hdma_status: DMAType::NoDMA,
@@ -119,14 +167,50 @@ impl MMU {
 		};
 
 		fill_random(&mut res.wram, 42);
-		res.determine_mode();
-		res.set_initial();
+
+		// The boot ROM itself always runs in color mode.
+		if !res.boot_rom_mapped() {
+			res.determine_mode();
+			res.set_initial();
+
+			if res.gbmode == GbMode::ColorAsClassic {
+				let palettes = Palettes::for_cartridge(&*res.mbc);
+				res.gpu.load_compat_palettes(palettes);
+			}
+		}
 
 		Ok(res)
 	}
 
+	pub const fn boot_rom_mapped(&self) -> bool {
+		!self.boot_rom.is_empty()
+	}
+
+	/// The CGB boot ROM keeps access to the color registers after selecting
+	/// DMG compatibility mode, so it can still set OPRI.
+	const fn cgb_registers(&self) -> bool {
+		matches!(self.gbmode, GbMode::Color) || (self.model.is_cgb() && self.boot_rom_mapped())
+	}
+
+	fn unmap_boot_rom(&mut self) {
+		self.boot_rom = Vec::new();
+	}
+
+	/// KEY0 is how the CGB boot ROM picks between color and DMG compatibility
+	/// mode for the cartridge. It can't be written once the boot ROM is gone.
+	const fn write_key0(&mut self, value: u8) {
+		let mode = if matches!(value & 0x04, 0x04) {
+			GbMode::ColorAsClassic
+		} else {
+			GbMode::Color
+		};
+
+		self.gbmode = mode;
+		self.gpu.gbmode = mode;
+	}
+
 	fn determine_mode(&mut self) {
-		let mode = match self.rb(0x0143) & 0x80 {
+		let mode = match self.mbc.read_rom(0x0143) & 0x80 {
 			0x80 => GbMode::Color,
 			_ => GbMode::ColorAsClassic,
 		};
@@ -136,13 +220,53 @@ impl MMU {
 	}
 
 	fn set_initial(&mut self) {
+		self.intf = 0x01;
+		match self.model {
+			Model::Dmg0 => self.timer.set_divider(0x18),
+			Model::Dmg | Model::Mgb => self.timer.set_divider(0xAB),
+			_ => {}
+		}
+
 		self.wb(0xFF05, 0);
 		self.wb(0xFF06, 0);
 		self.wb(0xFF07, 0);
+		self.set_initial_sound();
+		self.wb(0xFF40, 0x91);
+		self.wb(0xFF42, 0);
+		self.wb(0xFF43, 0);
+		self.wb(0xFF45, 0);
+		self.wb(0xFF47, 0xFC);
+		self.wb(0xFF48, 0xFF);
+		self.wb(0xFF49, 0xFF);
+		self.wb(0xFF4A, 0);
+		self.wb(0xFF4B, 0);
+
+		// The CGB boot ROM switches classic games to sprite priority by X
+		// coordinate.
+		if self.gbmode == GbMode::ColorAsClassic {
+			self.gpu.wb(0xFF6C, 0x01);
+		}
+	}
+
+	pub fn enable_sound(&mut self, player: Box<dyn AudioPlayer>) {
+		self.sound = Some(if self.model.is_cgb() {
+			Sound::cgb(player)
+		} else {
+			Sound::dmg(player)
+		});
+
+		self.set_initial_sound();
+	}
+
+	fn set_initial_sound(&mut self) {
+		// Everything besides NR52 ignores writes while the APU is off.
+		self.wb(0xFF26, 0xF1);
 		self.wb(0xFF10, 0x80);
 		self.wb(0xFF11, 0xBF);
 		self.wb(0xFF12, 0xF3);
-		self.wb(0xFF14, 0xBF);
+		// The SGB boot ROM doesn't play the startup sound, so channel 1 is
+		// left off there.
+		self.wb(0xFF14, if self.model.is_sgb() { 0x3F } else { 0xBF });
 		self.wb(0xFF16, 0x3F);
 		self.wb(0xFF16, 0x3F);
 		self.wb(0xFF17, 0);
@@ -157,40 +281,42 @@ impl MMU {
 		self.wb(0xFF23, 0xBF);
 		self.wb(0xFF24, 0x77);
 		self.wb(0xFF25, 0xF3);
-		self.wb(0xFF26, 0xF1);
-		self.wb(0xFF40, 0x91);
-		self.wb(0xFF42, 0);
-		self.wb(0xFF43, 0);
-		self.wb(0xFF45, 0);
-		self.wb(0xFF47, 0xFC);
-		self.wb(0xFF48, 0xFF);
-		self.wb(0xFF49, 0xFF);
-		self.wb(0xFF4A, 0);
-		self.wb(0xFF4B, 0);
 	}
 
-	#[expect(unreachable_patterns, reason = "false positive")]
 	pub fn rb(&mut self, address: u16) -> u8 {
+		if let Some(value) = self.oamdma_conflict(address) {
+			return value;
+		}
+
+		if matches!(address, 0xFE00..=0xFEFF) {
+			self.trigger_oam_bug(OamCorruption::Read);
+		}
+
+		self.read(address)
+	}
+
+	fn read(&mut self, address: u16) -> u8 {
 		match address {
-			0x0000..=0x7FFF => self.mbc.read_rom(address),
-			0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4F | 0xFF68..=0xFF6B => {
-				self.gpu.rb(address)
+			0x0000..=0x00FF | 0x0200..=0x08FF if (address as usize) < self.boot_rom.len() => {
+				self.boot_rom[address as usize]
 			}
+			0x0000..=0x7FFF => self.mbc.read_rom(address),
 			0xA000..=0xBFFF => self.mbc.read_ram(address),
 			0xC000..=0xCFFF | 0xE000..=0xEFFF => self.wram[address as usize & 0x0FFF],
 			0xD000..=0xDFFF | 0xF000..=0xFDFF => {
 				self.wram[(self.wram_bank * 0x1000) | address as usize & 0x0FFF]
 			}
-			0xFF00 => self.keypad.rb(),
+			0xFF00 => match &self.sgb {
+				Some(sgb) => sgb.read_joypad(self.keypad.rb()),
+				None => self.keypad.rb(),
+			},
+			// The high speed bit only exists in color mode.
+			0xFF02 if !matches!(self.gbmode, GbMode::Color) => self.serial.rb(address) | 0x02,
 			0xFF01..=0xFF02 => self.serial.rb(address),
 			0xFF04..=0xFF07 => self.timer.rb(address),
 			0xFF0F => self.intf | 0b1110_0000,
 			0xFF10..=0xFF3F => self.sound.as_mut().map_or(0xFF, |s| s.rb(address)),
-			0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF6C | 0xFF70
-				if !matches!(self.gbmode, GbMode::Color) =>
-			{
-				0xFF
-			}
+			0xFF4D | 0xFF4F | 0xFF51..=0xFF56 | 0xFF6C | 0xFF70 if !self.cgb_registers() => 0xFF,
 			0xFF72..=0xFF73 | 0xFF75..=0xFF77 if matches!(self.gbmode, GbMode::Classic) => 0xFF,
 			0xFF4D => {
 				0b0111_1110
@@ -200,40 +326,56 @@ impl MMU {
 						0
 					}) | u8::from(self.speed_switch_req)
 			}
+			0xFF46 => self.oamdma_reg,
 			0xFF51..=0xFF55 => self.hdma_read(address),
+			0xFF56 => self.infrared.rb(address),
 			0xFF70 => self.wram_bank as u8,
 			0xFF72..=0xFF73 => self.undocumented_cgb_regs[address as usize - 0xFF72],
 			0xFF75 => self.undocumented_cgb_regs[2] | 0b1000_1111,
 			0xFF76..=0xFF77 => 0x00,
+			0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4F | 0xFF68..=0xFF6C => {
+				self.gpu.rb(address)
+			}
 			0xFF80..=0xFFFE => self.zram[address as usize & 0x007F],
 			0xFFFF => self.inte,
 			_ => 0xFF,
 		}
 	}
 
-	pub fn rw(&mut self, address: u16) -> u16 {
-		u16::from(self.rb(address)) | (u16::from(self.rb(address + 1)) << 8)
-	}
-
-	#[expect(unreachable_patterns, reason = "false positive")]
 	pub fn wb(&mut self, address: u16, value: u8) {
+		if self.oamdma_conflict(address).is_some() {
+			return;
+		}
+
+		if matches!(address, 0xFE00..=0xFEFF) {
+			self.trigger_oam_bug(OamCorruption::Write);
+		}
+
 		match address {
 			0x0000..=0x7FFF => self.mbc.write_rom(address, value),
-			0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4F | 0xFF68..=0xFF6B => {
-				self.gpu.wb(address, value);
-			}
 			0xA000..=0xBFFF => self.mbc.write_ram(address, value),
 			0xC000..=0xCFFF | 0xE000..=0xEFFF => self.wram[address as usize & 0x0FFF] = value,
 			0xD000..=0xDFFF | 0xF000..=0xFDFF => {
 				self.wram[(self.wram_bank * 0x1000) | (address as usize & 0x0FFF)] = value;
 			}
-			0xFF00 => self.keypad.wb(value),
+			0xFF00 => {
+				self.keypad.wb(value);
+				if let Some(sgb) = self.sgb.as_mut() {
+					sgb.write_joypad(value);
+				}
+			}
+			0xFF02 if !matches!(self.gbmode, GbMode::Color) => {
+				self.serial.wb(address, value & !0x02);
+			}
 			0xFF01..=0xFF02 => self.serial.wb(address, value),
 			0xFF04..=0xFF07 => self.timer.wb(address, value),
 			0xFF10..=0xFF3F => self.sound.as_mut().map_or((), |s| s.wb(address, value)),
-			0xFF46 => self.oamdma(value),
-			0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF6C | 0xFF70 | 0xFF76..=0xFF77
-				if self.gbmode != GbMode::Color => {}
+			0xFF46 => {
+				self.oamdma_reg = value;
+				self.oamdma_delay = 2;
+			}
+			0xFF4D | 0xFF4F | 0xFF51..=0xFF56 | 0xFF6C | 0xFF70 | 0xFF76..=0xFF77
+				if !self.cgb_registers() => {}
 			0xFF72..=0xFF73 | 0xFF75..=0xFF77 if self.gbmode == GbMode::Classic => {}
 			0xFF4D => {
 				if value & 0x1 == 0x1 {
@@ -241,7 +383,9 @@ impl MMU {
 				}
 			}
 			0xFF51..=0xFF55 => self.hdma_write(address, value),
+			0xFF56 => self.infrared.wb(address, value),
 			0xFF0F => self.intf = value,
+			0xFF50 if value != 0 && self.boot_rom_mapped() => self.unmap_boot_rom(),
 			0xFF70 => {
 				self.wram_bank = match value & 0x7 {
 					0 => 1,
@@ -250,6 +394,10 @@ impl MMU {
 			}
 			0xFF72..=0xFF73 => self.undocumented_cgb_regs[address as usize - 0xFF72] = value,
 			0xFF75 => self.undocumented_cgb_regs[2] = value,
+			0xFF4C if self.boot_rom_mapped() && self.model.is_cgb() => self.write_key0(value),
+			0x8000..=0x9FFF | 0xFE00..=0xFE9F | 0xFF40..=0xFF4F | 0xFF68..=0xFF6C => {
+				self.gpu.wb(address, value);
+			}
 			0xFF80..=0xFFFE => self.zram[address as usize & 0x007F] = value,
 			0xFFFF => self.inte = value,
 			_ => {}
@@ -257,31 +405,42 @@ impl MMU {
 	}
 
 	pub fn do_cycle(&mut self, ticks: u32) -> u32 {
+		if let Some(corruption) = self.oam_bug.take() {
+			self.gpu.corrupt_oam(corruption);
+		}
+
 		let cpudivider = self.gbspeed as u32;
 		let vramticks = self.perform_vramdma();
 		let gputicks = ticks / cpudivider + vramticks;
 		let cputicks = ticks + vramticks * cpudivider;
 
+		for _ in 0..cputicks / 4 {
+			self.oamdma_step();
+		}
+
 		self.timer.do_cycle(cputicks);
 		self.intf |= mem::take(&mut self.timer.interrupt);
 
 		self.intf |= mem::take(&mut self.keypad.interrupt);
 
 		self.gpu.do_cycle(gputicks);
+		if matches!(self.gpu.interrupt & 0x01, 0x01)
+			&& let Some(sgb) = self.sgb.as_mut()
+		{
+			sgb.end_frame(&self.gpu.shades);
+		}
+
 		self.intf |= mem::take(&mut self.gpu.interrupt);
 
 		() = self.sound.as_mut().map_or((), |s| s.do_cycle(gputicks));
 
+		self.serial.do_cycle(cputicks);
+		self.serial.advance(gputicks);
 		self.intf |= mem::take(&mut self.serial.interrupt);
 
 		gputicks
 	}
 
-	pub fn ww(&mut self, address: u16, value: u16) {
-		self.wb(address, (value & 0xFF) as u8);
-		self.wb(address + 1, (value >> 8) as u8);
-	}
-
 	pub const fn switch_speed(&mut self) {
 		if self.speed_switch_req {
 			if matches!(self.gbspeed, GbSpeed::Double) {
@@ -294,11 +453,61 @@ impl MMU {
 		self.speed_switch_req = false;
 	}
 
-	fn oamdma(&mut self, value: u8) {
-		let base = u16::from(value) << 8;
-		for i in 0..0xA0 {
-			let b = self.rb(base + i);
-			self.wb(0xFE00 + i, b);
+	/// Remembers how the CPU touched OAM during this M-cycle, which corrupts
+	/// it once the cycle ends. Only the DMG family has this bug.
+	const fn trigger_oam_bug(&mut self, corruption: OamCorruption) {
+		if self.model.is_cgb() {
+			return;
+		}
+
+		self.oam_bug = Some(match (self.oam_bug, corruption) {
+			(Some(OamCorruption::Read), OamCorruption::Write) => OamCorruption::ReadIncDec,
+			(Some(OamCorruption::Write), _) => OamCorruption::Write,
+			(_, corruption) => corruption,
+		});
+	}
+
+	/// Copies one byte per M-cycle. A transfer starts two M-cycles after
+	/// 0xFF46 is written, and one already running carries on until then.
+	fn oamdma_step(&mut self) {
+		if self.oamdma_delay > 0 {
+			self.oamdma_delay -= 1;
+			if self.oamdma_delay == 0 {
+				// Sources past 0xDFFF read from work RAM again.
+				self.oamdma_src = match u16::from(self.oamdma_reg) << 8 {
+					src @ 0xE000.. => src - 0x2000,
+					src => src,
+				};
+				self.oamdma_pos = Some(0);
+			}
+		}
+
+		if let Some(pos) = self.oamdma_pos {
+			self.oamdma_byte = self.read(self.oamdma_src + pos);
+			self.gpu.write_oam(pos, self.oamdma_byte);
+			self.oamdma_pos = (pos < 0x9F).then_some(pos + 1);
+		}
+	}
+
+	/// While OAM DMA runs, OAM reads 0xFF and the CPU sees the byte being
+	/// transferred instead of whatever it accesses on the same bus.
+	fn oamdma_conflict(&self, address: u16) -> Option<u8> {
+		self.oamdma_pos?;
+
+		if matches!(address, 0xFE00..=0xFEFF) {
+			return Some(0xFF);
+		}
+
+		let bus = self.memory_bus(address)?;
+		(Some(bus) == self.memory_bus(self.oamdma_src)).then_some(self.oamdma_byte)
+	}
+
+	const fn memory_bus(&self, address: u16) -> Option<MemoryBus> {
+		match address {
+			0x8000..=0x9FFF => Some(MemoryBus::Video),
+			0xC000..=0xFDFF if self.model.is_cgb() => Some(MemoryBus::Work),
+			0x0000..=0x7FFF | 0xA000..=0xFDFF => Some(MemoryBus::External),
+			_ => None,
 		}
 	}
 
@@ -387,7 +596,7 @@ impl MMU {
 	fn perform_vramdma_row(&mut self) {
 		let mmu_src = self.hdma_src;
 		for j in 0..0x10 {
-			let b = self.rb(mmu_src + j);
+			let b = self.read(mmu_src + j);
 			self.gpu.wb(self.hdma_dst + j, b);
 		}
 		self.hdma_src += 0x10;
@@ -401,6 +610,59 @@ impl MMU {
 	}
 }
 
+impl Bus for MMU {
+	fn rb(&mut self, address: u16) -> u8 {
+		self.rb(address)
+	}
+
+	fn wb(&mut self, address: u16, value: u8) {
+		self.wb(address, value);
+	}
+
+	fn tick(&mut self, ticks: u32) -> u32 {
+		self.do_cycle(ticks)
+	}
+
+	fn pending_interrupts(&self) -> u8 {
+		self.inte & self.intf & 0x1F
+	}
+
+	fn acknowledge_interrupt(&mut self, n: u32) {
+		self.intf &= !(1 << n);
+	}
+
+	/// Performs an armed CGB speed switch, or enters low-power mode until a
+	/// button is pressed. DIV is reset in both cases.
+	fn stop(&mut self) -> bool {
+		self.timer.wb(0xFF04, 0);
+
+		if self.speed_switch_req {
+			self.switch_speed();
+			false
+		} else {
+			true
+		}
+	}
+
+	fn any_button_pressed(&self) -> bool {
+		self.keypad.any_pressed()
+	}
+
+	fn inc_dec(&mut self, address: u16) {
+		if matches!(address, 0xFE00..=0xFEFF) {
+			self.trigger_oam_bug(OamCorruption::Write);
+		}
+	}
+}
+
+/// The separate buses the CPU and DMA units access memory through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MemoryBus {
+	External,
+	Video,
+	Work,
+}
+
 #[derive(PartialEq, Eq, Serialize, Deserialize)]
 enum DMAType {
 	NoDMA,
@@ -408,6 +670,22 @@ enum DMAType {
 	HDMA,
 }
 
+/// Checks that a boot ROM image has the right size for `model`. The DMG
+/// family uses 256 bytes, while CGB boot ROMs have another 2 KiB mapped
+/// from 0x0200 onwards.
+fn check_boot_rom(model: Model, boot_rom: Option<Vec<u8>>) -> StrResult<Vec<u8>> {
+	let Some(boot_rom) = boot_rom else {
+		return Ok(Vec::new());
+	};
+
+	let expected = if model.is_cgb() { 0x900 } else { 0x100 };
+	if boot_rom.len() == expected {
+		Ok(boot_rom)
+	} else {
+		Err("boot rom has the wrong size for this model")
+	}
+}
+
 fn fill_random(slice: &mut [u8], start: u32) {
 	const A: u32 = 1_103_515_245;
 	const C: u32 = 12345;
@@ -418,3 +696,104 @@ fn fill_random(slice: &mut [u8], start: u32) {
 		*v = ((x >> 23) & 0xFF) as u8;
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::MMU;
+	use crate::{Model, bus::Bus, gb_mode::GbMode, mbc};
+
+	fn classic() -> MMU {
+		let cart = mbc::get_mbc(vec![0; 0x8000], true).unwrap();
+		let mut mmu = MMU::classic(cart, Model::Dmg, None, None).unwrap();
+		mmu.gpu.set_restrict_access(false);
+		mmu
+	}
+
+	fn corrupt_row_5(mut mmu: MMU) -> Vec<u8> {
+		for i in 0..0xA0 {
+			mmu.gpu.wb(0xFE00 + i, i as u8);
+		}
+
+		while mmu.gpu.oam_row() != Some(5) {
+			mmu.do_cycle(4);
+		}
+
+		mmu.inc_dec(0xFE10);
+		mmu.do_cycle(4);
+
+		(0xFE20..0xFE30).map(|a| mmu.gpu.rb(a)).collect()
+	}
+
+	#[test]
+	fn oamdma_timing() {
+		let mut mmu = classic();
+		for i in 0..0xA0 {
+			mmu.wb(0xC100 + i, i as u8);
+		}
+
+		mmu.wb(0xFF80, 0x12);
+		mmu.wb(0xFF46, 0xC1);
+		assert_eq!(mmu.rb(0xFF46), 0xC1);
+
+		mmu.do_cycle(4);
+		assert_eq!(mmu.rb(0xFE00), 0x00);
+
+		mmu.do_cycle(4);
+		assert_eq!(mmu.rb(0xFE00), 0xFF);
+		assert_eq!(mmu.rb(0xFF80), 0x12);
+
+		// Reads on the same bus see the byte being transferred instead.
+		mmu.do_cycle(4 * 9);
+		assert_eq!(mmu.rb(0x0000), 0x09);
+		assert_eq!(mmu.rb(0xC000), 0x09);
+
+		mmu.do_cycle(4 * 149);
+		assert_eq!(mmu.rb(0xFE00), 0xFF);
+
+		mmu.do_cycle(4);
+		for i in 0..0xA0 {
+			assert_eq!(mmu.rb(0xFE00 + i), i as u8);
+		}
+	}
+
+	#[test]
+	fn key0_selects_mode() {
+		let cart = mbc::get_mbc(vec![0; 0x8000], true).unwrap();
+		let mut mmu = MMU::cgb(cart, Model::Cgb, Some(vec![0; 0x900]), None).unwrap();
+		assert_eq!(mmu.gbmode, GbMode::Color);
+
+		mmu.wb(0xFF4C, 0x04);
+		assert_eq!(mmu.gbmode, GbMode::ColorAsClassic);
+		assert_eq!(mmu.gpu.gbmode, GbMode::ColorAsClassic);
+		assert!(mmu.gpu.oam_priority());
+
+		// The boot ROM can still pick the DMG sprite order afterwards.
+		assert_eq!(mmu.rb(0xFF6C), 0xFE);
+		mmu.wb(0xFF6C, 0x01);
+		assert_eq!(mmu.rb(0xFF6C), 0xFF);
+		assert!(!mmu.gpu.oam_priority());
+
+		// Once the boot ROM is unmapped, KEY0 is locked.
+		mmu.wb(0xFF50, 0x01);
+		mmu.wb(0xFF4C, 0x80);
+		assert_eq!(mmu.gbmode, GbMode::ColorAsClassic);
+		assert!(!mmu.gpu.oam_priority());
+
+		// Without a boot ROM, classic games start out the way it leaves them.
+		let cart = mbc::get_mbc(vec![0; 0x8000], true).unwrap();
+		let mmu = MMU::cgb(cart, Model::Cgb, None, None).unwrap();
+		assert_eq!(mmu.gbmode, GbMode::ColorAsClassic);
+		assert!(!mmu.gpu.oam_priority());
+	}
+
+	#[test]
+	fn oam_bug() {
+		let expected: Vec<u8> = (0x20..0x28).chain(0x20..0x28).collect();
+		assert_eq!(corrupt_row_5(classic()), expected);
+
+		let cart = mbc::get_mbc(vec![0; 0x8000], true).unwrap();
+		let mut mmu = MMU::cgb(cart, Model::Cgb, None, None).unwrap();
+		mmu.gpu.set_restrict_access(false);
+		assert_eq!(corrupt_row_5(mmu), (0x20..0x30).collect::<Vec<u8>>());
+	}
+}
@@ -1,7 +1,7 @@
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
-use super::gb_mode::GbMode;
+use super::gb_mode::{GbMode, Model};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Registers {
@@ -18,19 +18,46 @@ pub struct Registers {
 }
 
 impl Registers {
-	pub fn new(mode: GbMode) -> Self {
-		match mode {
-			GbMode::Classic => Self {
-				a: 0x01,
-				f: CpuFlags::C | CpuFlags::H | CpuFlags::Z,
-				b: 0x00,
-				c: 0x13,
-				d: 0x00,
-				e: 0xD8,
-				h: 0x01,
-				l: 0x4D,
-				pc: 0x0100,
-				sp: 0xFFFE,
+	/// The register values left behind by the boot ROM of `model`.
+	pub fn new(model: Model, mode: GbMode) -> Self {
+		let mut reg = match mode {
+			GbMode::Classic => match model {
+				Model::Dmg0 => Self {
+					a: 0x01,
+					f: CpuFlags::empty(),
+					b: 0xFF,
+					c: 0x13,
+					d: 0x00,
+					e: 0xC1,
+					h: 0x84,
+					l: 0x03,
+					pc: 0x0100,
+					sp: 0xFFFE,
+				},
+				Model::Sgb | Model::Sgb2 => Self {
+					a: 0x01,
+					f: CpuFlags::empty(),
+					b: 0x00,
+					c: 0x14,
+					d: 0x00,
+					e: 0x00,
+					h: 0xC0,
+					l: 0x60,
+					pc: 0x0100,
+					sp: 0xFFFE,
+				},
+				_ => Self {
+					a: 0x01,
+					f: CpuFlags::C | CpuFlags::H | CpuFlags::Z,
+					b: 0x00,
+					c: 0x13,
+					d: 0x00,
+					e: 0xD8,
+					h: 0x01,
+					l: 0x4D,
+					pc: 0x0100,
+					sp: 0xFFFE,
+				},
 			},
 			GbMode::ColorAsClassic => Self {
 				a: 0x11,
@@ -56,6 +83,35 @@ impl Registers {
 				pc: 0x0100,
 				sp: 0xFFFE,
 			},
+		};
+
+		match model {
+			// The pocket models identify themselves through A.
+			Model::Mgb | Model::Sgb2 => reg.a = 0xFF,
+			// The AGB boot ROM ends with an `INC B`, which also clears Z.
+			Model::Agb => {
+				reg.b = 0x01;
+				reg.f = CpuFlags::empty();
+			}
+			_ => {}
+		}
+
+		reg
+	}
+
+	/// The register values before the boot ROM has run.
+	pub const fn power_on() -> Self {
+		Self {
+			a: 0x00,
+			f: CpuFlags::empty(),
+			b: 0x00,
+			c: 0x00,
+			d: 0x00,
+			e: 0x00,
+			h: 0x00,
+			l: 0x00,
+			pc: 0x0000,
+			sp: 0x0000,
 		}
 	}
 
@@ -77,13 +133,13 @@ impl Registers {
 
 	pub const fn hld(&mut self) -> u16 {
 		let res = self.hl();
-		self.set_hl(res - 1);
+		self.set_hl(res.wrapping_sub(1));
 		res
 	}
 
 	pub const fn hli(&mut self) -> u16 {
 		let res = self.hl();
-		self.set_hl(res + 1);
+		self.set_hl(res.wrapping_add(1));
 		res
 	}
 
@@ -141,11 +197,11 @@ bitflags! {
 
 #[cfg(test)]
 mod tests {
-	use super::{CpuFlags, GbMode, Registers};
+	use super::{CpuFlags, GbMode, Model, Registers};
 
 	#[test]
 	fn wide_registers() {
-		let mut reg = Registers::new(GbMode::Classic);
+		let mut reg = Registers::new(Model::Dmg, GbMode::Classic);
 		reg.a = 0x12;
 		reg.set_f(0x23);
 		reg.b = 0x34;
@@ -172,7 +228,7 @@ mod tests {
 
 	#[test]
 	fn flags() {
-		let mut reg = Registers::new(GbMode::Classic);
+		let mut reg = Registers::new(Model::Dmg, GbMode::Classic);
 		let flags = [CpuFlags::C, CpuFlags::H, CpuFlags::N, CpuFlags::Z];
 
 		assert_eq!(reg.f.bits() & 0x0F, 0);
@@ -190,7 +246,7 @@ mod tests {
 
 	#[test]
 	fn hl_special() {
-		let mut reg = Registers::new(GbMode::Classic);
+		let mut reg = Registers::new(Model::Dmg, GbMode::Classic);
 		reg.set_hl(0x1234);
 		assert_eq!(reg.hl(), 0x1234);
 		assert_eq!(reg.hld(), 0x1234);
@@ -201,4 +257,27 @@ mod tests {
 		assert_eq!(reg.hli(), 0x1233);
 		assert_eq!(reg.hl(), 0x1234);
 	}
+
+	#[test]
+	fn post_boot_values() {
+		let reg = Registers::new(Model::Dmg, GbMode::Classic);
+		assert_eq!(reg.af(), 0x01B0);
+		assert_eq!(reg.bc(), 0x0013);
+
+		let reg = Registers::new(Model::Mgb, GbMode::Classic);
+		assert_eq!(reg.af(), 0xFFB0);
+
+		let reg = Registers::new(Model::Sgb, GbMode::Classic);
+		assert_eq!(reg.af(), 0x0100);
+		assert_eq!(reg.hl(), 0xC060);
+
+		let reg = Registers::new(Model::Cgb, GbMode::Color);
+		assert_eq!(reg.af(), 0x1180);
+		assert_eq!(reg.bc(), 0x0000);
+
+		let reg = Registers::new(Model::Agb, GbMode::Color);
+		assert_eq!(reg.af(), 0x1100);
+		assert_eq!(reg.bc(), 0x0100);
+		assert_eq!(reg.de(), 0xFF56);
+	}
 }
@@ -4,6 +4,10 @@ use serde::{Deserialize, Serialize};
 pub struct Serial {
 	data: u8,
 	control: u8,
+	incoming: u8,
+	bits_left: u8,
+	clock: u32,
+	period: u32,
 	#[serde(skip)]
 	callback: Option<Box<dyn SerialCallback>>,
 	pub interrupt: u8,
@@ -14,6 +18,10 @@ impl Serial {
 		Self {
 			data: 0,
 			control: 0,
+			incoming: 0,
+			bits_left: 0,
+			clock: 0,
+			period: 512,
 			callback: None,
 			interrupt: 0,
 		}
@@ -21,10 +29,8 @@ impl Serial {
 
 	pub fn with_callback(cb: Box<dyn SerialCallback>) -> Self {
 		Self {
-			data: 0,
-			control: 0,
 			callback: Some(cb),
-			interrupt: 0,
+			..Self::new()
 		}
 	}
 
@@ -33,12 +39,23 @@ impl Serial {
 			0xFF01 => self.data = v,
 			0xFF02 => {
 				self.control = v;
-				if matches!(v & 0x81, 0x81)
-					&& let Some(callback) = &mut self.callback
-					&& let Some(result) = callback.call(self.data)
-				{
-					self.data = result;
-					self.interrupt = 0x8;
+				self.bits_left = 0;
+				self.clock = 0;
+
+				if let Some(callback) = &mut self.callback {
+					callback.write_control(v);
+				}
+
+				// The byte coming back is known as soon as the partner sees ours,
+				// but it's still shifted in one bit at a time.
+				if matches!(v & 0x81, 0x81) {
+					self.incoming = self
+						.callback
+						.as_mut()
+						.and_then(|callback| callback.call(self.data))
+						.unwrap_or(0xFF);
+					self.bits_left = 8;
+					self.period = if matches!(v & 0x02, 0x02) { 16 } else { 512 };
 				}
 			}
 			_ => panic!("serial does not handle write address {a:4X}"),
@@ -48,11 +65,58 @@ impl Serial {
 	pub fn rb(&self, a: u16) -> u8 {
 		match a {
 			0xFF01 => self.data,
-			0xFF02 => self.control | 0b0111_1110,
+			0xFF02 => self.control | 0b0111_1100,
 			_ => panic!("serial does not handle read address {a:4X}"),
 		}
 	}
 
+	/// Shifts a bit every 512 cycles, or every 16 with the CGB high speed bit
+	/// set. Transfers on an external clock wait for the partner to start
+	/// clocking, and then shift at whatever rate the partner uses.
+	pub fn do_cycle(&mut self, ticks: u32) {
+		if !matches!(self.control & 0x80, 0x80) {
+			return;
+		}
+
+		if !matches!(self.control & 0x01, 0x01) && self.bits_left == 0 {
+			if let Some(callback) = &mut self.callback
+				&& let Some((received, period)) = callback.external_clock(self.data)
+			{
+				self.incoming = received;
+				self.bits_left = 8;
+				self.period = period;
+			}
+
+			return;
+		}
+
+		self.clock += ticks;
+		while self.bits_left > 0 && self.clock >= self.period {
+			self.clock -= self.period;
+			self.data = (self.data << 1) | (self.incoming >> 7);
+			self.incoming <<= 1;
+			self.bits_left -= 1;
+
+			if self.bits_left == 0 {
+				self.finish();
+			}
+		}
+	}
+
+	/// Lets the other side know how much time passed, in cycles at normal
+	/// speed.
+	pub fn advance(&mut self, ticks: u32) {
+		if let Some(callback) = &mut self.callback {
+			callback.advance(ticks);
+		}
+	}
+
+	const fn finish(&mut self) {
+		self.control &= 0x7F;
+		self.clock = 0;
+		self.interrupt = 0x08;
+	}
+
 	pub fn set_callback(&mut self, cb: Box<dyn SerialCallback>) {
 		self.callback = Some(cb);
 	}
@@ -63,5 +127,91 @@ impl Serial {
 }
 
 pub trait SerialCallback: Send {
+	/// Called when a transfer starts on this side's clock, with the byte being
+	/// sent. Returns the byte sent back, or `None` if nothing answered, in
+	/// which case 0xFF is received.
 	fn call(&mut self, value: u8) -> Option<u8>;
+
+	/// Called whenever the game writes SC, before a transfer on this side's
+	/// clock starts. Clearing bit 7 or setting bit 0 stops any wait for the
+	/// partner's clock.
+	fn write_control(&mut self, _control: u8) {}
+
+	/// Polled while a transfer waits on the partner's clock, with the byte
+	/// that will be sent. Returns the byte coming in once the partner starts
+	/// clocking, along with how many cycles each of its bits takes.
+	fn external_clock(&mut self, _value: u8) -> Option<(u8, u32)> {
+		None
+	}
+
+	/// Called as emulated time passes, in cycles at normal speed, for links
+	/// that need to keep in step with the other side.
+	fn advance(&mut self, _ticks: u32) {}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Serial, SerialCallback};
+
+	struct Echo;
+
+	impl SerialCallback for Echo {
+		fn call(&mut self, value: u8) -> Option<u8> {
+			Some(!value)
+		}
+
+		fn external_clock(&mut self, value: u8) -> Option<(u8, u32)> {
+			Some((value.rotate_left(4), 512))
+		}
+	}
+
+	#[test]
+	fn internal_clock_timing() {
+		let mut serial = Serial::with_callback(Box::new(Echo));
+		serial.wb(0xFF01, 0x0F);
+		serial.wb(0xFF02, 0x81);
+
+		serial.do_cycle(512 * 4);
+		assert_eq!(serial.rb(0xFF01), 0xFF);
+		assert_eq!(serial.rb(0xFF02), 0xFD);
+
+		serial.do_cycle(512 * 4 - 4);
+		assert_eq!(serial.interrupt, 0);
+
+		serial.do_cycle(4);
+		assert_eq!(serial.rb(0xFF01), 0xF0);
+		assert_eq!(serial.rb(0xFF02), 0x7D);
+		assert_eq!(serial.interrupt, 0x08);
+	}
+
+	#[test]
+	fn high_speed_and_no_partner() {
+		let mut serial = Serial::new();
+		serial.wb(0xFF01, 0x12);
+		serial.wb(0xFF02, 0x83);
+
+		serial.do_cycle(16 * 8);
+		assert_eq!(serial.rb(0xFF01), 0xFF);
+		assert_eq!(serial.interrupt, 0x08);
+	}
+
+	#[test]
+	fn external_clock_waits() {
+		let mut serial = Serial::new();
+		serial.wb(0xFF02, 0x80);
+		serial.do_cycle(512 * 16);
+		assert_eq!(serial.interrupt, 0);
+		assert_eq!(serial.rb(0xFF02), 0xFC);
+
+		// Once the partner starts clocking, the bits come in at its rate.
+		serial.set_callback(Box::new(Echo));
+		serial.wb(0xFF01, 0x12);
+		serial.do_cycle(4);
+		serial.do_cycle(512 * 8 - 4);
+		assert_eq!(serial.interrupt, 0);
+
+		serial.do_cycle(4);
+		assert_eq!(serial.rb(0xFF01), 0x21);
+		assert_eq!(serial.interrupt, 0x08);
+	}
 }
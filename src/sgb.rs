@@ -0,0 +1,563 @@
+use std::{cmp::Ordering, mem};
+
+use serde::{Deserialize, Serialize};
+
+use super::gpu::{SCREEN_H, SCREEN_W};
+
+/// The width of the Super Game Boy output, including the border.
+pub const SGB_SCREEN_W: usize = 256;
+/// The height of the Super Game Boy output, including the border.
+pub const SGB_SCREEN_H: usize = 224;
+
+const ATTR_W: usize = SCREEN_W / 8;
+const ATTR_H: usize = SCREEN_H / 8;
+const ATTR_FILE_SIZE: usize = ATTR_W * ATTR_H / 4;
+const ATTR_FILES: usize = 45;
+const TRANSFER_SIZE: usize = 0x1000;
+const BORDER_X: usize = (SGB_SCREEN_W - SCREEN_W) / 2;
+const BORDER_Y: usize = (SGB_SCREEN_H - SCREEN_H) / 2;
+
+/// The colors the SGB starts with before a game sets its own.
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+/// The Super Game Boy side of the joypad port. Games talk to it by
+/// bit-banging command packets through 0xFF00, and it colorizes the
+/// monochrome picture per 8x8 cell and draws a border around it.
+#[derive(Serialize, Deserialize)]
+pub struct Sgb {
+	joypad: u8,
+	packet_bit: Option<u8>,
+	packet: [u8; 16],
+	command: Vec<u8>,
+	players: u8,
+	player: u8,
+	palettes: [[u16; 4]; 4],
+	system_palettes: Vec<u16>,
+	#[serde(with = "serde_arrays")]
+	attributes: [u8; ATTR_W * ATTR_H],
+	attribute_files: Vec<u8>,
+	border_tiles: Vec<u8>,
+	border_map: Vec<u8>,
+	border_palettes: [[u16; 16]; 4],
+	mask: Mask,
+	transfer: Option<Transfer>,
+	pub data: Vec<u8>,
+	pub border_data: Option<Vec<u8>>,
+}
+
+impl Sgb {
+	pub fn new() -> Self {
+		Self {
+			joypad: 0x30,
+			packet_bit: None,
+			packet: [0; 16],
+			command: Vec::new(),
+			players: 1,
+			player: 0,
+			palettes: [DEFAULT_PALETTE; 4],
+			system_palettes: vec![0; TRANSFER_SIZE / 2],
+			attributes: [0; ATTR_W * ATTR_H],
+			attribute_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+			border_tiles: vec![0; TRANSFER_SIZE * 2],
+			border_map: vec![0; TRANSFER_SIZE],
+			border_palettes: [[0; 16]; 4],
+			mask: Mask::Cancel,
+			transfer: None,
+			data: vec![0xFF; SCREEN_W * SCREEN_H * 3],
+			border_data: None,
+		}
+	}
+
+	/// Also renders the 256x224 picture with the border around the screen.
+	pub fn enable_border(&mut self) {
+		self.border_data = Some(vec![0; SGB_SCREEN_W * SGB_SCREEN_H * 3]);
+	}
+
+	/// Replaces the lower nibble read from 0xFF00 with the current joypad ID
+	/// while neither button row is selected. Only the first controller has
+	/// buttons connected.
+	pub const fn read_joypad(&self, value: u8) -> u8 {
+		if matches!(value & 0x30, 0x30) {
+			(value & 0xF0) | (0x0F - self.player)
+		} else if self.player != 0 {
+			value | 0x0F
+		} else {
+			value
+		}
+	}
+
+	pub fn write_joypad(&mut self, value: u8) {
+		let value = value & 0x30;
+		let old = mem::replace(&mut self.joypad, value);
+
+		// Games poll each controller in turn once multiplayer is requested,
+		// and the SGB steps to the next one whenever P15 goes back high.
+		if self.packet_bit.is_none() && matches!(old & 0x20, 0x00) && matches!(value, 0x30) {
+			self.player = (self.player + 1) % self.players;
+		}
+
+		if matches!(value, 0x00) {
+			self.packet_bit = Some(0);
+			self.packet = [0; 16];
+			return;
+		}
+
+		if old != 0x30 {
+			return;
+		}
+
+		match (self.packet_bit, value) {
+			// The stop bit after 128 data bits has to be a zero.
+			(Some(128), _) => {
+				self.packet_bit = None;
+				if matches!(value, 0x20) {
+					self.receive_packet();
+				}
+			}
+			(Some(bit), 0x10 | 0x20) => {
+				if matches!(value, 0x10) {
+					self.packet[bit as usize / 8] |= 1 << (bit % 8);
+				}
+
+				self.packet_bit = Some(bit + 1);
+			}
+			_ => {}
+		}
+	}
+
+	fn receive_packet(&mut self) {
+		if self.command.is_empty() && matches!(self.packet[0] & 0x07, 0) {
+			// A command needs at least one packet.
+			self.packet[0] |= 0x01;
+		}
+
+		self.command.extend_from_slice(&self.packet);
+		let packets = (self.command[0] & 0x07) as usize;
+		if self.command.len() >= packets * 16 {
+			let command = mem::take(&mut self.command);
+			self.run_command(&command);
+		}
+	}
+
+	fn run_command(&mut self, data: &[u8]) {
+		match data[0] >> 3 {
+			0x00 => self.set_palettes(0, 1, data),
+			0x01 => self.set_palettes(2, 3, data),
+			0x02 => self.set_palettes(0, 3, data),
+			0x03 => self.set_palettes(1, 2, data),
+			0x04 => self.attr_blk(data),
+			0x05 => self.attr_lin(data),
+			0x06 => self.attr_div(data),
+			0x07 => self.attr_chr(data),
+			0x0A => self.pal_set(data),
+			0x0B => self.transfer = Some(Transfer::Palettes),
+			0x11 => {
+				self.players = match data[1] & 0x03 {
+					1 => 2,
+					3 => 4,
+					_ => 1,
+				};
+				self.player = 0;
+			}
+			0x13 => self.transfer = Some(Transfer::Tiles(usize::from(data[1] & 0x01))),
+			0x14 => self.transfer = Some(Transfer::Map),
+			0x15 => self.transfer = Some(Transfer::Attributes),
+			0x16 => self.attr_set(data[1]),
+			0x17 => self.mask = Mask::from(data[1]),
+			// Sound, SNES programs and the other commands are not emulated.
+			_ => {}
+		}
+	}
+
+	fn set_palettes(&mut self, a: usize, b: usize, data: &[u8]) {
+		let color0 = read_color(data, 1);
+		for palette in &mut self.palettes {
+			palette[0] = color0;
+		}
+
+		for i in 1..4 {
+			self.palettes[a][i] = read_color(data, 1 + i * 2);
+			self.palettes[b][i] = read_color(data, 7 + i * 2);
+		}
+	}
+
+	fn attr_blk(&mut self, data: &[u8]) {
+		let count = usize::from(data[1] & 0x1F);
+		for block in data[2..].chunks_exact(6).take(count) {
+			let control = block[0] & 0x07;
+			let inside = block[1] & 0x03;
+			let outside = (block[1] >> 4) & 0x03;
+			// With only one of inside or outside set, the border follows it.
+			let (control, border) = match control {
+				0x01 => (0x03, inside),
+				0x04 => (0x06, outside),
+				_ => (control, (block[1] >> 2) & 0x03),
+			};
+
+			let (x1, y1, x2, y2) = (block[2], block[3], block[4], block[5]);
+			for y in 0..ATTR_H as u8 {
+				for x in 0..ATTR_W as u8 {
+					let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+						(control & 0x01 != 0).then_some(inside)
+					} else if x >= x1 && x <= x2 && y >= y1 && y <= y2 {
+						(control & 0x02 != 0).then_some(border)
+					} else {
+						(control & 0x04 != 0).then_some(outside)
+					};
+
+					if let Some(palette) = palette {
+						self.attributes[y as usize * ATTR_W + x as usize] = palette;
+					}
+				}
+			}
+		}
+	}
+
+	fn attr_lin(&mut self, data: &[u8]) {
+		let count = usize::from(data[1]);
+		for &line in data[2..].iter().take(count) {
+			let palette = (line >> 5) & 0x03;
+			let n = usize::from(line & 0x1F);
+			if matches!(line & 0x80, 0x80) {
+				if n < ATTR_H {
+					self.attributes[n * ATTR_W..(n + 1) * ATTR_W].fill(palette);
+				}
+			} else if n < ATTR_W {
+				for y in 0..ATTR_H {
+					self.attributes[y * ATTR_W + n] = palette;
+				}
+			}
+		}
+	}
+
+	fn attr_div(&mut self, data: &[u8]) {
+		let control = data[1];
+		let split = usize::from(data[2]);
+		let after = control & 0x03;
+		let before = (control >> 2) & 0x03;
+		let on = (control >> 4) & 0x03;
+		let horizontal = matches!(control & 0x40, 0x40);
+
+		for y in 0..ATTR_H {
+			for x in 0..ATTR_W {
+				let pos = if horizontal { y } else { x };
+				self.attributes[y * ATTR_W + x] = match pos.cmp(&split) {
+					Ordering::Less => before,
+					Ordering::Equal => on,
+					Ordering::Greater => after,
+				};
+			}
+		}
+	}
+
+	fn attr_chr(&mut self, data: &[u8]) {
+		let mut x = usize::from(data[1]);
+		let mut y = usize::from(data[2]);
+		let count = usize::from(u16::from_le_bytes([data[3], data[4]]));
+		let vertical = matches!(data[5], 1);
+
+		for i in 0..count.min(ATTR_W * ATTR_H) {
+			let Some(&byte) = data.get(6 + i / 4) else {
+				break;
+			};
+
+			if x < ATTR_W && y < ATTR_H {
+				self.attributes[y * ATTR_W + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+			}
+
+			if vertical {
+				y += 1;
+				if y >= ATTR_H {
+					y = 0;
+					x += 1;
+				}
+			} else {
+				x += 1;
+				if x >= ATTR_W {
+					x = 0;
+					y += 1;
+				}
+			}
+		}
+	}
+
+	fn pal_set(&mut self, data: &[u8]) {
+		for (i, palette) in self.palettes.iter_mut().enumerate() {
+			let index = usize::from(read_color(data, 1 + i * 2) & 0x01FF);
+			palette.copy_from_slice(&self.system_palettes[index * 4..index * 4 + 4]);
+		}
+
+		if matches!(data[9] & 0x80, 0x80) {
+			self.attr_set(data[9]);
+		}
+
+		if matches!(data[9] & 0x40, 0x40) {
+			self.mask = Mask::Cancel;
+		}
+	}
+
+	fn attr_set(&mut self, value: u8) {
+		let file = usize::from(value & 0x3F);
+		if file < ATTR_FILES {
+			let start = file * ATTR_FILE_SIZE;
+			for (i, cell) in self.attributes.iter_mut().enumerate() {
+				let byte = self.attribute_files[start + i / 4];
+				*cell = (byte >> (6 - (i % 4) * 2)) & 0x03;
+			}
+		}
+
+		if matches!(value & 0x40, 0x40) {
+			self.mask = Mask::Cancel;
+		}
+	}
+
+	/// Called once a frame has been drawn, with the shade of every pixel on
+	/// screen. Pending VRAM transfers read their data from this picture.
+	pub fn end_frame(&mut self, shades: &[u8]) {
+		if let Some(transfer) = self.transfer.take() {
+			let data = read_vram_transfer(shades);
+			match transfer {
+				Transfer::Palettes => {
+					for (color, bytes) in self.system_palettes.iter_mut().zip(data.chunks_exact(2))
+					{
+						*color = u16::from_le_bytes([bytes[0], bytes[1]]);
+					}
+				}
+				Transfer::Attributes => {
+					let len = self.attribute_files.len();
+					self.attribute_files.copy_from_slice(&data[..len]);
+				}
+				Transfer::Tiles(half) => {
+					self.border_tiles[half * TRANSFER_SIZE..(half + 1) * TRANSFER_SIZE]
+						.copy_from_slice(&data);
+				}
+				Transfer::Map => {
+					self.border_map.copy_from_slice(&data);
+					for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+						for (j, color) in palette.iter_mut().enumerate() {
+							*color = read_color(&data, 0x800 + (i * 16 + j) * 2);
+						}
+					}
+				}
+			}
+		}
+
+		match self.mask {
+			Mask::Cancel => {
+				for (i, &shade) in shades.iter().enumerate() {
+					let palette = self.attributes[(i / SCREEN_W / 8) * ATTR_W + (i % SCREEN_W) / 8];
+					let color = self.palettes[palette as usize][shade as usize];
+					set_rgb(&mut self.data, i, color);
+				}
+			}
+			Mask::Freeze => {}
+			Mask::Black => self.data.fill(0),
+			Mask::Color0 => {
+				for i in 0..SCREEN_W * SCREEN_H {
+					set_rgb(&mut self.data, i, self.palettes[0][0]);
+				}
+			}
+		}
+
+		self.draw_border();
+	}
+
+	fn draw_border(&mut self) {
+		let Some(border_data) = self.border_data.as_mut() else {
+			return;
+		};
+
+		let backdrop = self.palettes[0][0];
+		for y in 0..SGB_SCREEN_H {
+			for x in 0..SGB_SCREEN_W {
+				let i = y * SGB_SCREEN_W + x;
+				if (BORDER_X..BORDER_X + SCREEN_W).contains(&x)
+					&& (BORDER_Y..BORDER_Y + SCREEN_H).contains(&y)
+				{
+					let src = ((y - BORDER_Y) * SCREEN_W + x - BORDER_X) * 3;
+					border_data[i * 3..i * 3 + 3].copy_from_slice(&self.data[src..src + 3]);
+					continue;
+				}
+
+				let map = ((y / 8) * 32 + x / 8) * 2;
+				let entry = u16::from_le_bytes([self.border_map[map], self.border_map[map + 1]]);
+				let tile = usize::from(entry & 0xFF) * 32;
+				let palette = usize::from((entry >> 10) & 0x03);
+				let px = if matches!(entry & 0x4000, 0) {
+					7 - x % 8
+				} else {
+					x % 8
+				};
+				let py = if matches!(entry & 0x8000, 0) {
+					y % 8
+				} else {
+					7 - y % 8
+				};
+
+				let planes = [
+					self.border_tiles[tile + py * 2],
+					self.border_tiles[tile + py * 2 + 1],
+					self.border_tiles[tile + 16 + py * 2],
+					self.border_tiles[tile + 16 + py * 2 + 1],
+				];
+				let index = planes.iter().enumerate().fold(0, |index, (bit, plane)| {
+					index | (((plane >> px) & 1) << bit)
+				});
+
+				let color = match index {
+					0 => backdrop,
+					n => self.border_palettes[palette][n as usize],
+				};
+				set_rgb(border_data, i, color);
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Transfer {
+	Palettes,
+	Attributes,
+	Tiles(usize),
+	Map,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Mask {
+	Cancel,
+	Freeze,
+	Black,
+	Color0,
+}
+
+impl From<u8> for Mask {
+	fn from(value: u8) -> Self {
+		match value & 0x03 {
+			0 => Self::Cancel,
+			1 => Self::Freeze,
+			2 => Self::Black,
+			_ => Self::Color0,
+		}
+	}
+}
+
+fn read_color(data: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn set_rgb(data: &mut [u8], i: usize, color: u16) {
+	for (c, shift) in [0, 5, 10].into_iter().enumerate() {
+		let value = ((color >> shift) & 0x1F) as u8;
+		data[i * 3 + c] = (value << 3) | (value >> 2);
+	}
+}
+
+/// Rebuilds the 4 KiB of tile data a game shows on screen for a VRAM
+/// transfer. The SGB reads it back from the first 256 tiles in display
+/// order, with two bits per pixel.
+fn read_vram_transfer(shades: &[u8]) -> Vec<u8> {
+	let mut data = vec![0; TRANSFER_SIZE];
+	for (tile, bytes) in data.chunks_exact_mut(16).enumerate() {
+		let (tx, ty) = (tile % ATTR_W, tile / ATTR_W);
+		for row in 0..8 {
+			for px in 0..8 {
+				let shade = shades[(ty * 8 + row) * SCREEN_W + tx * 8 + px];
+				bytes[row * 2] |= (shade & 0x01) << (7 - px);
+				bytes[row * 2 + 1] |= ((shade >> 1) & 0x01) << (7 - px);
+			}
+		}
+	}
+
+	data
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Mask, SCREEN_W, Sgb, read_vram_transfer};
+
+	fn send_packet(sgb: &mut Sgb, packet: [u8; 16]) {
+		sgb.write_joypad(0x00);
+		sgb.write_joypad(0x30);
+		for bit in 0..128 {
+			let one = packet[bit / 8] & (1 << (bit % 8)) != 0;
+			sgb.write_joypad(if one { 0x10 } else { 0x20 });
+			sgb.write_joypad(0x30);
+		}
+
+		sgb.write_joypad(0x20);
+		sgb.write_joypad(0x30);
+	}
+
+	#[test]
+	fn palette_packet() {
+		let mut sgb = Sgb::new();
+		let mut packet = [0; 16];
+		packet[0] = 0x01;
+		packet[1..3].copy_from_slice(&0x7FFFu16.to_le_bytes());
+		packet[3..5].copy_from_slice(&0x001Fu16.to_le_bytes());
+		packet[9..11].copy_from_slice(&0x03E0u16.to_le_bytes());
+		send_packet(&mut sgb, packet);
+
+		assert_eq!(sgb.palettes[0][..2], [0x7FFF, 0x001F]);
+		assert_eq!(sgb.palettes[1][..2], [0x7FFF, 0x03E0]);
+		assert_eq!(sgb.palettes[3][0], 0x7FFF);
+	}
+
+	#[test]
+	fn multiplayer_joypad_ids() {
+		let mut sgb = Sgb::new();
+		let mut packet = [0; 16];
+		packet[0] = 0x11 << 3 | 0x01;
+		packet[1] = 0x01;
+		send_packet(&mut sgb, packet);
+		assert_eq!(sgb.read_joypad(0xFF), 0xFF);
+
+		sgb.write_joypad(0x10);
+		sgb.write_joypad(0x30);
+		assert_eq!(sgb.read_joypad(0xFF), 0xFE);
+		assert_eq!(sgb.read_joypad(0xDE), 0xDF);
+
+		sgb.write_joypad(0x10);
+		sgb.write_joypad(0x30);
+		assert_eq!(sgb.read_joypad(0xFF), 0xFF);
+	}
+
+	#[test]
+	fn attribute_block() {
+		let mut sgb = Sgb::new();
+		let mut packet = [0; 16];
+		packet[0] = 0x04 << 3 | 0x01;
+		packet[1] = 1;
+		packet[2..8].copy_from_slice(&[0x07, 0b10_01_11, 2, 2, 5, 5]);
+		send_packet(&mut sgb, packet);
+
+		assert_eq!(sgb.attributes[0], 2);
+		assert_eq!(sgb.attributes[2 * 20 + 2], 1);
+		assert_eq!(sgb.attributes[3 * 20 + 3], 3);
+	}
+
+	#[test]
+	fn mask_and_colorize() {
+		let mut sgb = Sgb::new();
+		let mut shades = vec![0; SCREEN_W * 144];
+		shades[0] = 3;
+		sgb.end_frame(&shades);
+		assert_eq!(sgb.data[..3], [0x31, 0x18, 0x52]);
+		assert_eq!(sgb.data[3..6], [0xFF, 0xEF, 0xCE]);
+
+		sgb.mask = Mask::Black;
+		sgb.end_frame(&shades);
+		assert!(sgb.data.iter().all(|&b| b == 0));
+	}
+
+	#[test]
+	fn vram_transfer_round_trip() {
+		let mut shades = vec![0; SCREEN_W * 144];
+		// The second tile's first row: 0b1010_0000 in the low plane and
+		// 0b1000_0000 in the high plane.
+		shades[8] = 3;
+		shades[10] = 1;
+		let data = read_vram_transfer(&shades);
+		assert_eq!(data[16..18], [0xA0, 0x80]);
+	}
+}
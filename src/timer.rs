@@ -1,86 +1,237 @@
 use serde::{Deserialize, Serialize};
 
+/// DIV and TIMA, both driven by a single 16-bit counter that runs at the
+/// full clock rate. DIV is its upper byte, and TIMA is incremented whenever
+/// the counter bit selected by TAC falls from 1 to 0.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Timer {
-	divider: u8,
+	system_counter: u16,
 	counter: u8,
 	modulo: u8,
-	enabled: bool,
-	step: u32,
-	internalcnt: u32,
-	internaldiv: u32,
+	control: u8,
+	overflow: bool,
+	reloading: bool,
 	pub interrupt: u8,
 }
 
 impl Timer {
 	pub const fn new() -> Self {
 		Self {
-			divider: 0,
+			system_counter: 0,
 			counter: 0,
 			modulo: 0,
-			enabled: false,
-			step: 1024,
-			internalcnt: 0,
-			internaldiv: 0,
+			control: 0,
+			overflow: false,
+			reloading: false,
 			interrupt: 0,
 		}
 	}
 
+	pub const fn set_divider(&mut self, value: u8) {
+		self.system_counter = (value as u16) << 8;
+	}
+
 	pub fn rb(&self, a: u16) -> u8 {
 		match a {
-			0xFF04 => self.divider,
+			0xFF04 => (self.system_counter >> 8) as u8,
 			0xFF05 => self.counter,
 			0xFF06 => self.modulo,
-			0xFF07 => {
-				0xF8 | (if self.enabled { 0x4 } else { 0 })
-					| (match self.step {
-						16 => 1,
-						64 => 2,
-						256 => 3,
-						_ => 0,
-					})
-			}
+			0xFF07 => 0xF8 | self.control,
 			_ => panic!("timer does not handle read {a:4X}"),
 		}
 	}
 
 	pub fn wb(&mut self, a: u16, v: u8) {
 		match a {
-			0xFF04 => self.divider = 0,
-			0xFF05 => self.counter = v,
-			0xFF06 => self.modulo = v,
-			0xFF07 => {
-				self.enabled = !matches!(v & 0x4, 0);
-				self.step = match v & 0x4 {
-					1 => 16,
-					2 => 64,
-					3 => 256,
-					_ => 1024,
+			0xFF04 => {
+				let old = self.signal();
+				self.system_counter = 0;
+				self.detect_edge(old);
+			}
+			// TIMA is loaded from TMA during this cycle, which wins over the write.
+			0xFF05 if self.reloading => {}
+			0xFF05 => {
+				self.counter = v;
+				self.overflow = false;
+			}
+			0xFF06 => {
+				self.modulo = v;
+				if self.reloading {
+					self.counter = v;
 				}
 			}
+			0xFF07 => {
+				let old = self.signal();
+				self.control = v & 0x07;
+				self.detect_edge(old);
+			}
 			_ => panic!("timer does not handle write {a:4X}"),
 		}
 	}
 
 	pub const fn do_cycle(&mut self, ticks: u32) {
-		self.internaldiv += ticks;
-		while self.internaldiv >= 256 {
-			self.divider = self.divider.wrapping_add(1);
-			self.internaldiv -= 256;
+		let mut cycles = ticks / 4;
+		while cycles > 0 {
+			self.step();
+			cycles -= 1;
 		}
+	}
 
-		if self.enabled {
-			self.internalcnt += ticks;
+	/// Runs a single M-cycle. After TIMA overflows it reads 0 for one cycle,
+	/// and is only reloaded from TMA, with the interrupt requested, on the
+	/// cycle after.
+	const fn step(&mut self) {
+		self.reloading = false;
+		if self.overflow {
+			self.overflow = false;
+			self.counter = self.modulo;
+			self.interrupt |= 0x04;
+			self.reloading = true;
+		}
 
-			while self.internalcnt >= self.step {
-				self.counter = self.counter.wrapping_add(1);
-				if matches!(self.counter, 0) {
-					self.counter = self.modulo;
-					self.interrupt |= 0x04;
-				}
+		let old = self.signal();
+		self.system_counter = self.system_counter.wrapping_add(4);
+		self.detect_edge(old);
+	}
 
-				self.internalcnt -= self.step;
-			}
+	const fn signal(&self) -> bool {
+		let bit = match self.control & 0x03 {
+			1 => 3,
+			2 => 5,
+			3 => 7,
+			_ => 9,
+		};
+
+		matches!(self.control & 0x04, 0x04) && (self.system_counter >> bit) & 1 == 1
+	}
+
+	const fn detect_edge(&mut self, old: bool) {
+		if old && !self.signal() {
+			let (counter, overflow) = self.counter.overflowing_add(1);
+			self.counter = counter;
+			self.overflow |= overflow;
 		}
 	}
 }
+
+#[cfg(test)]
+#[allow(clippy::needless_range_loop)]
+mod tests {
+	use super::Timer;
+
+	#[test]
+	fn divider() {
+		let mut timer = Timer::new();
+		timer.do_cycle(252);
+		assert_eq!(timer.rb(0xFF04), 0x00);
+
+		timer.do_cycle(4);
+		assert_eq!(timer.rb(0xFF04), 0x01);
+
+		timer.wb(0xFF04, 0x55);
+		assert_eq!(timer.rb(0xFF04), 0x00);
+	}
+
+	#[test]
+	fn frequencies() {
+		let steps: [u32; 4] = [1024, 16, 64, 256];
+
+		for i in 0..steps.len() {
+			let mut timer = Timer::new();
+			timer.wb(0xFF07, 0x04 | i as u8);
+			assert_eq!(timer.rb(0xFF07), 0xFC | i as u8);
+
+			timer.do_cycle(steps[i] - 4);
+			assert_eq!(timer.rb(0xFF05), 0);
+
+			timer.do_cycle(4);
+			assert_eq!(timer.rb(0xFF05), 1);
+
+			timer.do_cycle(steps[i] * 3);
+			assert_eq!(timer.rb(0xFF05), 4);
+		}
+	}
+
+	#[test]
+	fn div_write_falling_edge() {
+		let mut timer = Timer::new();
+		timer.wb(0xFF07, 0x05);
+		timer.do_cycle(8);
+		assert_eq!(timer.rb(0xFF05), 0);
+
+		// Bit 3 of the counter is set, so resetting it counts as an edge.
+		timer.wb(0xFF04, 0);
+		assert_eq!(timer.rb(0xFF05), 1);
+
+		timer.do_cycle(4);
+		timer.wb(0xFF04, 0);
+		assert_eq!(timer.rb(0xFF05), 1);
+	}
+
+	#[test]
+	fn tac_write_falling_edge() {
+		let mut timer = Timer::new();
+		timer.wb(0xFF07, 0x05);
+		timer.do_cycle(8);
+
+		// Disabling the timer while the selected bit is set increments it.
+		timer.wb(0xFF07, 0x01);
+		assert_eq!(timer.rb(0xFF05), 1);
+
+		// So does switching to a frequency whose bit is clear.
+		timer.wb(0xFF07, 0x05);
+		timer.wb(0xFF07, 0x06);
+		assert_eq!(timer.rb(0xFF05), 2);
+	}
+
+	#[test]
+	fn overflow_delay() {
+		let mut timer = Timer::new();
+		timer.wb(0xFF06, 0x42);
+		timer.wb(0xFF05, 0xFF);
+		timer.wb(0xFF07, 0x05);
+
+		timer.do_cycle(16);
+		assert_eq!(timer.rb(0xFF05), 0x00);
+		assert_eq!(timer.interrupt, 0);
+
+		timer.do_cycle(4);
+		assert_eq!(timer.rb(0xFF05), 0x42);
+		assert_eq!(timer.interrupt, 0x04);
+	}
+
+	#[test]
+	fn write_cancels_overflow() {
+		let mut timer = Timer::new();
+		timer.wb(0xFF06, 0x42);
+		timer.wb(0xFF05, 0xFF);
+		timer.wb(0xFF07, 0x05);
+
+		timer.do_cycle(16);
+		timer.wb(0xFF05, 0x10);
+
+		timer.do_cycle(4);
+		assert_eq!(timer.rb(0xFF05), 0x10);
+		assert_eq!(timer.interrupt, 0);
+	}
+
+	#[test]
+	fn writes_while_reloading() {
+		let mut timer = Timer::new();
+		timer.wb(0xFF06, 0x42);
+		timer.wb(0xFF05, 0xFF);
+		timer.wb(0xFF07, 0x05);
+		timer.do_cycle(20);
+
+		// TIMA writes are ignored, and TMA writes go through to TIMA.
+		timer.wb(0xFF05, 0x10);
+		assert_eq!(timer.rb(0xFF05), 0x42);
+
+		timer.wb(0xFF06, 0x24);
+		assert_eq!(timer.rb(0xFF05), 0x24);
+
+		timer.do_cycle(4);
+		timer.wb(0xFF05, 0x10);
+		assert_eq!(timer.rb(0xFF05), 0x10);
+	}
+}